@@ -0,0 +1,17 @@
+//! The firmware modules free of hardware and of embassy, built for the host
+//! to run their unit tests with `cargo test` in this directory.
+
+#[path = "../src/devices/bme280/compensation.rs"]
+pub mod compensation;
+
+#[path = "../src/devices/wind/averaging.rs"]
+pub mod averaging;
+
+#[path = "../src/meteo.rs"]
+pub mod meteo;
+
+#[path = "../src/crc.rs"]
+pub mod crc;
+
+#[path = "../src/history/ring.rs"]
+pub mod ring;
@@ -1,6 +1,10 @@
 MEMORY {
     BOOT2 : ORIGIN = 0x10000000, LENGTH = 0x100
-    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100
+    FLASH : ORIGIN = 0x10000100, LENGTH = 1536K - 0x100
+    /* Measurement history and persistent settings,   */
+    /* see src/devices/flash.rs                       */
+    LOG : ORIGIN = 0x10180000, LENGTH = 448K
+    STORAGE : ORIGIN = 0x101F0000, LENGTH = 64K
 
     /* Pick one of the two options for RAM layout     */
 
@@ -0,0 +1,189 @@
+//! Per-sensor linear calibration, persisted in flash
+
+use core::cell::RefCell;
+
+use defmt::{info, warn, Format};
+use embassy_sync::blocking_mutex::{raw::ThreadModeRawMutex, Mutex};
+use heapless::{String, Vec};
+
+use crate::{
+    crc::crc32,
+    devices::{
+        flash,
+        sensor::{Measurements, Quantity},
+    },
+};
+
+pub const MAX_CALIBRATIONS: usize = 16;
+
+const MAGIC: u32 = u32::from_le_bytes(*b"CALB");
+const VERSION: u8 = 1;
+/// Magic, version, count and two bytes of padding
+const HEADER_SIZE: usize = 8;
+/// Sensor name, quantity, padding, gain and offset
+const RECORD_SIZE: usize = 16 + 4 + 4 + 4;
+const STORED_SIZE: usize = HEADER_SIZE + MAX_CALIBRATIONS * RECORD_SIZE + 4;
+
+#[derive(Format, Clone, Copy, PartialEq, Eq)]
+pub enum CalibrationError {
+    Full,
+    Flash,
+}
+
+/// Maps a raw reading onto `raw * gain + offset`
+#[derive(Clone)]
+pub struct Calibration {
+    pub sensor: String<16>,
+    pub quantity: Quantity,
+    pub gain: f32,
+    pub offset: f32,
+}
+
+impl Calibration {
+    /// The calibration taking the raw readings of two points to their
+    /// reference values, if the raw readings differ
+    pub fn two_point(
+        sensor: String<16>,
+        quantity: Quantity,
+        (raw_low, reference_low): (f32, f32),
+        (raw_high, reference_high): (f32, f32),
+    ) -> Option<Self> {
+        if raw_low == raw_high {
+            return None;
+        }
+
+        let gain = (reference_high - reference_low) / (raw_high - raw_low);
+        Some(Self {
+            sensor,
+            quantity,
+            gain,
+            offset: reference_low - raw_low * gain,
+        })
+    }
+
+    fn is_identity(&self) -> bool {
+        self.gain == 1.0 && self.offset == 0.0
+    }
+
+    fn encode(&self, record: &mut [u8]) {
+        record[..self.sensor.len()].copy_from_slice(self.sensor.as_bytes());
+        record[16] = Quantity::ALL
+            .iter()
+            .position(|q| *q == self.quantity)
+            .unwrap_or_default() as u8;
+        record[20..24].copy_from_slice(&self.gain.to_le_bytes());
+        record[24..28].copy_from_slice(&self.offset.to_le_bytes());
+    }
+
+    fn decode(record: &[u8]) -> Option<Self> {
+        let name = &record[..16];
+        let length = name.iter().position(|b| *b == 0).unwrap_or(name.len());
+        let sensor = core::str::from_utf8(&name[..length]).ok()?;
+
+        Some(Self {
+            sensor: String::try_from(sensor).ok()?,
+            quantity: *Quantity::ALL.get(record[16] as usize)?,
+            gain: f32::from_le_bytes(record[20..24].try_into().ok()?),
+            offset: f32::from_le_bytes(record[24..28].try_into().ok()?),
+        })
+    }
+}
+
+static CALIBRATIONS: Mutex<ThreadModeRawMutex, RefCell<Vec<Calibration, MAX_CALIBRATIONS>>> =
+    Mutex::new(RefCell::new(Vec::new()));
+
+fn load() -> Option<Vec<Calibration, MAX_CALIBRATIONS>> {
+    let mut stored = [0u8; STORED_SIZE];
+    flash::read(flash::CALIBRATION, &mut stored).ok()?;
+
+    let (data, crc) = stored.split_at(STORED_SIZE - 4);
+    if u32::from_le_bytes(data[..4].try_into().ok()?) != MAGIC
+        || data[4] != VERSION
+        || u32::from_le_bytes(crc.try_into().ok()?) != crc32(data)
+    {
+        return None;
+    }
+
+    data[HEADER_SIZE..]
+        .chunks_exact(RECORD_SIZE)
+        .take(data[5] as usize)
+        .map(Calibration::decode)
+        .collect()
+}
+
+fn save(calibrations: &[Calibration]) -> Result<(), CalibrationError> {
+    let mut stored = [0u8; STORED_SIZE];
+    stored[..4].copy_from_slice(&MAGIC.to_le_bytes());
+    stored[4] = VERSION;
+    stored[5] = calibrations.len() as u8;
+    for (calibration, record) in calibrations
+        .iter()
+        .zip(stored[HEADER_SIZE..].chunks_exact_mut(RECORD_SIZE))
+    {
+        calibration.encode(record);
+    }
+
+    let crc = crc32(&stored[..STORED_SIZE - 4]);
+    stored[STORED_SIZE - 4..].copy_from_slice(&crc.to_le_bytes());
+
+    flash::write_sector(flash::CALIBRATION, &stored).map_err(|e| {
+        warn!("Saving calibrations failed: {}", e);
+        CalibrationError::Flash
+    })
+}
+
+/// Restores the calibrations saved in flash
+pub fn init() {
+    match load() {
+        Some(calibrations) => {
+            info!("Loaded {} sensor calibrations", calibrations.len());
+            CALIBRATIONS.lock(|cell| cell.replace(calibrations));
+        }
+        None => info!("No sensor calibrations saved"),
+    }
+}
+
+/// Stores and saves a calibration, replacing any for the same sensor and
+/// quantity. An identity calibration removes it instead.
+pub fn set(calibration: Calibration) -> Result<(), CalibrationError> {
+    CALIBRATIONS.lock(|cell| {
+        let mut calibrations = cell.borrow_mut();
+        let existing = calibrations
+            .iter()
+            .position(|c| c.sensor == calibration.sensor && c.quantity == calibration.quantity);
+
+        match existing {
+            Some(i) if calibration.is_identity() => {
+                calibrations.swap_remove(i);
+            }
+            Some(i) => calibrations[i] = calibration,
+            None if calibration.is_identity() => return Ok(()),
+            None => calibrations
+                .push(calibration)
+                .map_err(|_| CalibrationError::Full)?,
+        }
+
+        save(&calibrations)
+    })
+}
+
+/// Calls `f` with every stored calibration
+pub fn for_each<F: FnMut(&Calibration)>(mut f: F) {
+    CALIBRATIONS.lock(|cell| cell.borrow().iter().for_each(&mut f));
+}
+
+/// Calibrates the measurements of `sensor` in place, leaving their raw
+/// values untouched
+pub fn apply(sensor: &str, measurements: &mut Measurements) {
+    CALIBRATIONS.lock(|cell| {
+        for calibration in cell.borrow().iter().filter(|c| c.sensor == sensor) {
+            for measurement in measurements
+                .iter_mut()
+                .filter(|m| m.quantity == calibration.quantity)
+            {
+                let value = measurement.raw_as_f32() * calibration.gain + calibration.offset;
+                measurement.set_f32(value);
+            }
+        }
+    });
+}
@@ -0,0 +1,327 @@
+//! Station settings, persisted in flash. Until first changed, the compiled
+//! defaults apply, with the network settings of `secrets.rs`.
+
+use core::net::Ipv4Addr;
+
+use defmt::{info, warn};
+use embassy_net::Ipv4Cidr;
+use embassy_sync::{blocking_mutex::raw::ThreadModeRawMutex, mutex::Mutex};
+use heapless::{String, Vec};
+
+use crate::{crc::crc32, devices::flash};
+
+const MAGIC: u32 = u32::from_le_bytes(*b"CONF");
+/// Fields are only ever appended, bumping the version. Records of older
+/// versions leave the fields added since at their defaults, those of newer
+/// ones have the fields unknown here ignored.
+const VERSION: u8 = 4;
+/// Magic, version, padding and payload length
+const HEADER_SIZE: usize = 8;
+const MAX_PAYLOAD: usize = 512;
+const STORED_SIZE: usize = HEADER_SIZE + MAX_PAYLOAD + 4;
+
+/// How the station gets its IP address
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum IpMode {
+    Static,
+    Dhcp,
+}
+
+impl IpMode {
+    pub fn key(&self) -> &'static str {
+        match self {
+            IpMode::Static => "static",
+            IpMode::Dhcp => "dhcp",
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<Self> {
+        [IpMode::Static, IpMode::Dhcp]
+            .into_iter()
+            .find(|m| m.key() == key)
+    }
+}
+
+/// Networks known besides the main one
+pub const MAX_KNOWN: usize = 3;
+
+/// A network the station may join instead of the main one
+#[derive(Clone, PartialEq, Eq)]
+pub struct KnownNetwork {
+    pub ssid: String<32>,
+    pub password: String<64>,
+    /// Of the networks in reach, the one of the highest is joined
+    pub priority: u8,
+}
+
+/// How the station joins the network
+#[derive(Clone, PartialEq, Eq)]
+pub struct Network {
+    pub ssid: String<32>,
+    /// WPA2 passphrase, empty for open networks
+    pub password: String<64>,
+    pub priority: u8,
+    pub known: Vec<KnownNetwork, MAX_KNOWN>,
+    /// How to get an address, on whichever network is joined
+    pub mode: IpMode,
+    /// The address and gateway of static mode
+    pub address: Ipv4Cidr,
+    pub gateway: Option<Ipv4Addr>,
+}
+
+impl Network {
+    const EMPTY: Self = Self {
+        ssid: String::new(),
+        password: String::new(),
+        priority: 0,
+        known: Vec::new(),
+        mode: IpMode::Static,
+        address: Ipv4Cidr::new(Ipv4Addr::UNSPECIFIED, 0),
+        gateway: None,
+    };
+
+    /// The main network, as a known one
+    pub fn main(&self) -> KnownNetwork {
+        KnownNetwork {
+            ssid: self.ssid.clone(),
+            password: self.password.clone(),
+            priority: self.priority,
+        }
+    }
+
+    /// The main network first, then the others known
+    pub fn all(&self) -> impl Iterator<Item = KnownNetwork> + '_ {
+        core::iter::once(self.main()).chain(self.known.iter().cloned())
+    }
+
+    /// The settings of `secrets.rs`
+    fn compiled() -> Self {
+        let mut network = Self::EMPTY;
+        // Cannot fail - at most as long as an SSID or passphrase can be
+        let _ = network.ssid.push_str(crate::WIFI_NETWORK);
+        let _ = network.password.push_str(crate::WIFI_PASSWORD);
+        network.address = crate::PICO_IP;
+        network.gateway = Some(crate::GATEWAY_IP);
+        network
+    }
+}
+
+#[derive(Clone)]
+pub struct Config {
+    /// Shown on the pages, to tell stations apart
+    pub station_name: String<32>,
+    /// Station elevation above mean sea level, in metres
+    pub altitude: i16,
+    /// Rain gauge calibration, in µm of rain per bucket tip
+    pub rain_per_tip: u16,
+    /// Anemometer calibration, in mm/s of wind per pulse per second
+    pub wind_per_hz: u16,
+    /// Seconds between sensor readings
+    pub sample_interval: u16,
+    /// Seconds between readings written to the flash history
+    pub log_interval: u16,
+    pub network: Network,
+    /// Minutes without a connection before rebooting, 0 for never
+    pub outage_reboot: u16,
+}
+
+impl Config {
+    pub const DEFAULT: Self = Self {
+        station_name: String::new(),
+        altitude: 0,
+        // Common 0.011" tipping bucket gauges
+        rain_per_tip: 279,
+        // 2.4 km/h per Hz, as on most cup anemometers sold with them
+        wind_per_hz: 667,
+        sample_interval: 30,
+        // About a week of history
+        log_interval: 300,
+        network: Network::EMPTY,
+        outage_reboot: 0,
+    };
+
+    fn compiled() -> Self {
+        Self {
+            network: Network::compiled(),
+            ..Self::DEFAULT
+        }
+    }
+
+    fn encode(&self, payload: &mut Vec<u8, MAX_PAYLOAD>) -> Result<(), ()> {
+        let mut writer = Writer(payload);
+        writer.bytes(&self.altitude.to_le_bytes())?;
+        writer.bytes(&self.rain_per_tip.to_le_bytes())?;
+        writer.bytes(&self.wind_per_hz.to_le_bytes())?;
+        writer.bytes(&self.sample_interval.to_le_bytes())?;
+        writer.bytes(&self.log_interval.to_le_bytes())?;
+        writer.string(&self.network.ssid)?;
+        writer.string(&self.network.password)?;
+        writer.bytes(&self.network.address.address().octets())?;
+        writer.bytes(&[self.network.address.prefix_len()])?;
+        let gateway = self.network.gateway.unwrap_or(Ipv4Addr::UNSPECIFIED);
+        writer.bytes(&gateway.octets())?;
+        // Version 2
+        writer.string(&self.station_name)?;
+        writer.bytes(&[(self.network.mode == IpMode::Dhcp) as u8])?;
+        // Version 3
+        writer.bytes(&self.outage_reboot.to_le_bytes())?;
+        // Version 4
+        writer.bytes(&[self.network.priority, self.network.known.len() as u8])?;
+        for known in &self.network.known {
+            writer.string(&known.ssid)?;
+            writer.string(&known.password)?;
+            writer.bytes(&[known.priority])?;
+        }
+        Ok(())
+    }
+
+    /// Reads the fields a record of `version` has over the defaults
+    fn decode(version: u8, payload: &[u8]) -> Option<Self> {
+        let mut config = Self::compiled();
+        let mut reader = Reader(payload);
+
+        if version >= 1 {
+            config.altitude = i16::from_le_bytes(reader.bytes()?);
+            config.rain_per_tip = u16::from_le_bytes(reader.bytes()?);
+            config.wind_per_hz = u16::from_le_bytes(reader.bytes()?);
+            config.sample_interval = u16::from_le_bytes(reader.bytes()?);
+            config.log_interval = u16::from_le_bytes(reader.bytes()?);
+            config.network.ssid = reader.string()?;
+            config.network.password = reader.string()?;
+            let [address @ .., prefix_len] = reader.bytes::<5>()?;
+            config.network.address = Ipv4Cidr::new(Ipv4Addr::from(address), prefix_len);
+            let gateway = Ipv4Addr::from(reader.bytes::<4>()?);
+            config.network.gateway = (!gateway.is_unspecified()).then_some(gateway);
+        }
+        if version >= 2 {
+            config.station_name = reader.string()?;
+            let [dhcp] = reader.bytes()?;
+            config.network.mode = if dhcp != 0 {
+                IpMode::Dhcp
+            } else {
+                IpMode::Static
+            };
+        }
+        if version >= 3 {
+            config.outage_reboot = u16::from_le_bytes(reader.bytes()?);
+        }
+        if version >= 4 {
+            let [priority, count] = reader.bytes()?;
+            config.network.priority = priority;
+            config.network.known.clear();
+            for _ in 0..count {
+                let ssid = reader.string()?;
+                let password = reader.string()?;
+                let [priority] = reader.bytes()?;
+                let known = KnownNetwork {
+                    ssid,
+                    password,
+                    priority,
+                };
+                config.network.known.push(known).ok()?;
+            }
+        }
+
+        Some(config)
+    }
+}
+
+struct Writer<'a>(&'a mut Vec<u8, MAX_PAYLOAD>);
+
+impl Writer<'_> {
+    fn bytes(&mut self, bytes: &[u8]) -> Result<(), ()> {
+        self.0.extend_from_slice(bytes)
+    }
+
+    /// A string prefixed with its length
+    fn string(&mut self, string: &str) -> Result<(), ()> {
+        self.bytes(&[string.len() as u8])?;
+        self.bytes(string.as_bytes())
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn bytes<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let (bytes, rest) = self.0.split_first_chunk()?;
+        self.0 = rest;
+        Some(*bytes)
+    }
+
+    fn string<const N: usize>(&mut self) -> Option<String<N>> {
+        let [length] = self.bytes()?;
+        let (bytes, rest) = self.0.split_at_checked(length as usize)?;
+        self.0 = rest;
+        String::try_from(core::str::from_utf8(bytes).ok()?).ok()
+    }
+}
+
+/// The stored settings and the version they were saved in
+fn load() -> Option<(Config, u8)> {
+    let mut stored = [0u8; STORED_SIZE];
+    flash::read(flash::CONFIG, &mut stored).ok()?;
+
+    let version = stored[4];
+    let length = u16::from_le_bytes([stored[6], stored[7]]) as usize;
+    if u32::from_le_bytes(stored[..4].try_into().ok()?) != MAGIC || length > MAX_PAYLOAD {
+        return None;
+    }
+    let (data, rest) = stored.split_at(HEADER_SIZE + length);
+    if u32::from_le_bytes(rest[..4].try_into().ok()?) != crc32(data) {
+        return None;
+    }
+
+    Some((Config::decode(version, &data[HEADER_SIZE..])?, version))
+}
+
+fn save(config: &Config) -> Result<(), ()> {
+    let mut payload = Vec::new();
+    config.encode(&mut payload)?;
+
+    let mut stored: Vec<u8, STORED_SIZE> = Vec::new();
+    stored.extend_from_slice(&MAGIC.to_le_bytes())?;
+    stored.extend_from_slice(&[VERSION, 0])?;
+    stored.extend_from_slice(&(payload.len() as u16).to_le_bytes())?;
+    stored.extend_from_slice(&payload)?;
+    let crc = crc32(&stored);
+    stored.extend_from_slice(&crc.to_le_bytes())?;
+
+    flash::write_sector(flash::CONFIG, &stored).map_err(|e| warn!("Saving settings failed: {}", e))
+}
+
+static CONFIG: Mutex<ThreadModeRawMutex, Config> = Mutex::new(Config::DEFAULT);
+
+/// Restores the settings saved in flash, or applies the compiled defaults.
+/// Needs the flash initialized.
+pub async fn init() {
+    let config = match load() {
+        Some((config, version)) if version < VERSION => {
+            info!("Migrating settings from version {}", version);
+            // Kept in RAM regardless, saving is retried on the next change
+            let _ = save(&config);
+            config
+        }
+        Some((config, _)) => {
+            info!("Loaded settings");
+            config
+        }
+        None => {
+            info!("No settings saved, using defaults");
+            Config::compiled()
+        }
+    };
+
+    *CONFIG.lock().await = config;
+}
+
+pub async fn get() -> Config {
+    CONFIG.lock().await.clone()
+}
+
+/// Changes the settings and saves them. They apply even if saving fails.
+pub async fn update<F: FnOnce(&mut Config)>(f: F) -> Result<(), ()> {
+    let mut config = CONFIG.lock().await;
+    f(&mut config);
+    save(&config)
+}
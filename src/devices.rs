@@ -1,2 +1,13 @@
 pub mod rtc;
 pub mod dht;
+pub mod bme280;
+pub mod sht;
+pub mod bh1750;
+pub mod veml6075;
+pub mod rain;
+pub mod wind;
+pub mod system;
+pub mod adc;
+pub mod i2c;
+pub mod sensor;
+pub mod flash;
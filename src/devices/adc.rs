@@ -0,0 +1,23 @@
+use core::cell::RefCell;
+
+use embassy_rp::{
+    adc::{Adc, Blocking, Channel, Config},
+    peripherals::ADC,
+};
+use embassy_sync::blocking_mutex::{raw::ThreadModeRawMutex, Mutex};
+
+/// Conversions take 2 µs, so the ADC is used blocking and never held
+/// across an await, letting synchronous handlers share it with tasks
+static ADC: Mutex<ThreadModeRawMutex, RefCell<Option<Adc<'static, Blocking>>>> =
+    Mutex::new(RefCell::new(None));
+
+pub fn init(adc: ADC) {
+    ADC.lock(|cell| cell.replace(Some(Adc::new_blocking(adc, Config::default()))));
+}
+
+pub fn read(channel: &mut Channel) -> Option<u16> {
+    ADC.lock(|cell| {
+        let mut adc = cell.borrow_mut();
+        adc.as_mut()?.blocking_read(channel).ok()
+    })
+}
@@ -0,0 +1,160 @@
+use defmt::{info, warn, Format};
+use embassy_time::{Duration, Timer};
+use embedded_hal_async::i2c::I2c as _;
+
+use super::{
+    i2c::Device,
+    sensor::{AnySensor, Measurement, Measurements, Quantity, Sensor, SensorError, Unit},
+};
+
+/// ADDR pin low, 0x5c when high
+pub const DEFAULT_ADDRESS: u8 = 0x23;
+
+const POWER_ON: u8 = 0x01;
+/// One-time high resolution mode, 1 lx per count at the default MTreg
+const ONE_TIME_H_RESOLUTION: u8 = 0x20;
+/// One-time high resolution mode 2, 0.5 lx per count at the default MTreg
+const ONE_TIME_H_RESOLUTION_2: u8 = 0x21;
+const CHANGE_MT_HIGH: u8 = 0x40;
+const CHANGE_MT_LOW: u8 = 0x60;
+
+/// Measurement time register range and default
+const MT_MIN: u8 = 31;
+const MT_MAX: u8 = 254;
+const MT_DEFAULT: u8 = 69;
+/// Worst case conversion time at the default MTreg
+const MAX_CONVERSION_MS: u64 = 180;
+/// Raw count the auto-ranging aims for, leaving headroom for brightening
+const TARGET_COUNT: f32 = 30000.0;
+
+#[derive(Format, Clone, Copy, PartialEq, Eq)]
+pub enum Bh1750Error {
+    I2c,
+}
+
+pub struct Bh1750 {
+    i2c: Device,
+    address: u8,
+    /// Measurement time register, scales sensitivity and conversion time
+    mt: u8,
+    /// High resolution mode 2, doubling the counts per lux
+    half_lux: bool,
+}
+
+impl Bh1750 {
+    async fn command(&mut self, command: u8) -> Result<(), Bh1750Error> {
+        self.i2c
+            .write(self.address, &[command])
+            .await
+            .map_err(|_| Bh1750Error::I2c)
+    }
+
+    pub async fn new(i2c: Device, address: u8) -> Result<Self, Bh1750Error> {
+        let mut sensor = Self {
+            i2c,
+            address,
+            mt: MT_DEFAULT,
+            half_lux: true,
+        };
+        sensor.command(POWER_ON).await?;
+
+        Ok(sensor)
+    }
+
+    fn counts_per_lux(&self) -> f32 {
+        let counts = 1.2 * self.mt as f32 / MT_DEFAULT as f32;
+        if self.half_lux {
+            counts * 2.0
+        } else {
+            counts
+        }
+    }
+
+    async fn convert(&mut self) -> Result<u16, Bh1750Error> {
+        self.command(CHANGE_MT_HIGH | (self.mt >> 5)).await?;
+        self.command(CHANGE_MT_LOW | (self.mt & 0x1f)).await?;
+        self.command(if self.half_lux {
+            ONE_TIME_H_RESOLUTION_2
+        } else {
+            ONE_TIME_H_RESOLUTION
+        })
+        .await?;
+
+        Timer::after(Duration::from_millis(
+            MAX_CONVERSION_MS * self.mt as u64 / MT_DEFAULT as u64,
+        ))
+        .await;
+
+        let mut count = [0; 2];
+        self.i2c
+            .read(self.address, &mut count)
+            .await
+            .map_err(|_| Bh1750Error::I2c)?;
+        Ok(u16::from_be_bytes(count))
+    }
+
+    /// Picks the sensitivity that brings `lux` closest to the target count
+    fn adjust_range(&mut self, lux: f32) {
+        let counts_per_lux = TARGET_COUNT / lux.max(0.1);
+        // MTreg giving the wanted counts per lux in either mode
+        let mt_half_lux = counts_per_lux * MT_DEFAULT as f32 / 2.4;
+        let mt_full_lux = counts_per_lux * MT_DEFAULT as f32 / 1.2;
+
+        (self.half_lux, self.mt) = if mt_half_lux >= MT_MIN as f32 {
+            (true, mt_half_lux.min(MT_MAX as f32) as u8)
+        } else {
+            (false, mt_full_lux.max(MT_MIN as f32) as u8)
+        };
+    }
+
+    /// Measures illuminance in lx, re-ranging until the count is usable
+    pub async fn read(&mut self) -> Result<f32, Bh1750Error> {
+        let mut count = self.convert().await?;
+
+        // Saturated: retry once at the lowest sensitivity, good for ~120 klx
+        if count == u16::MAX && (self.half_lux || self.mt != MT_MIN) {
+            self.half_lux = false;
+            self.mt = MT_MIN;
+            count = self.convert().await?;
+        }
+
+        let lux = count as f32 / self.counts_per_lux();
+        self.adjust_range(lux);
+
+        Ok(lux)
+    }
+}
+
+impl Sensor for Bh1750 {
+    fn name(&self) -> &'static str {
+        "bh1750"
+    }
+
+    async fn measure(&mut self) -> Result<Measurements, SensorError> {
+        let lux = self.read().await.map_err(|e| {
+            warn!("BH1750 read failed: {}", e);
+            SensorError::ReadFailed
+        })?;
+
+        let mut measurements = Measurements::new();
+        // Cannot fail - fewer measurements than MAX_MEASUREMENTS
+        let _ = measurements.push(Measurement::new(
+            Quantity::Illuminance,
+            Unit::Lux,
+            (lux * 10.0 + 0.5) as i32,
+            1,
+        ));
+
+        Ok(measurements)
+    }
+}
+
+pub async fn init(i2c: Device) {
+    match Bh1750::new(i2c, DEFAULT_ADDRESS).await {
+        Ok(sensor) => {
+            info!("Found BH1750 at {:#x}", DEFAULT_ADDRESS);
+            super::sensor::register(AnySensor::Bh1750(sensor)).await;
+        }
+        Err(e) => warn!("No BH1750 found: {}", e),
+    }
+}
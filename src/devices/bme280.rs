@@ -0,0 +1,286 @@
+mod compensation;
+
+use compensation::Calibration;
+use defmt::{info, warn, Format};
+use embassy_time::{Duration, Timer};
+use embedded_hal_async::i2c::I2c as _;
+
+use super::{
+    i2c::Device,
+    sensor::{AnySensor, Measurement, Measurements, Quantity, Sensor, SensorError, Unit},
+};
+
+const ADDRESSES: [u8; 2] = [0x76, 0x77];
+
+const REG_CALIB_TP: u8 = 0x88;
+const REG_CHIP_ID: u8 = 0xd0;
+const REG_RESET: u8 = 0xe0;
+const REG_CALIB_H: u8 = 0xe1;
+const REG_CTRL_HUM: u8 = 0xf2;
+const REG_STATUS: u8 = 0xf3;
+const REG_CTRL_MEAS: u8 = 0xf4;
+const REG_CONFIG: u8 = 0xf5;
+const REG_DATA: u8 = 0xf7;
+
+const CHIP_ID_BME280: u8 = 0x60;
+const CHIP_IDS_BMP280: [u8; 3] = [0x56, 0x57, 0x58];
+
+const RESET_COMMAND: u8 = 0xb6;
+const MODE_FORCED: u8 = 0b01;
+const STATUS_MEASURING: u8 = 1 << 3;
+const STATUS_IM_UPDATE: u8 = 1 << 0;
+
+#[derive(Format, Clone, Copy, PartialEq, Eq)]
+pub enum Chip {
+    Bme280,
+    Bmp280,
+}
+
+#[derive(Format, Clone, Copy, PartialEq, Eq)]
+pub enum Bme280Error {
+    I2c,
+    UnknownChip(u8),
+    NotReady,
+}
+
+#[derive(Format, Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code)]
+pub enum Oversampling {
+    Skip = 0,
+    X1 = 1,
+    X2 = 2,
+    X4 = 3,
+    X8 = 4,
+    X16 = 5,
+}
+
+impl Oversampling {
+    fn samples(&self) -> u32 {
+        match self {
+            Oversampling::Skip => 0,
+            _ => 1 << (*self as u32 - 1),
+        }
+    }
+}
+
+#[derive(Format, Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code)]
+pub enum Filter {
+    Off = 0,
+    X2 = 1,
+    X4 = 2,
+    X8 = 3,
+    X16 = 4,
+}
+
+#[derive(Format, Clone, Copy)]
+pub struct Config {
+    pub temperature: Oversampling,
+    pub pressure: Oversampling,
+    pub humidity: Oversampling,
+    pub filter: Filter,
+}
+
+impl Default for Config {
+    /// Datasheet recommendation for weather monitoring: forced mode, one
+    /// sample per quantity, no IIR filter
+    fn default() -> Self {
+        Self {
+            temperature: Oversampling::X1,
+            pressure: Oversampling::X1,
+            humidity: Oversampling::X1,
+            filter: Filter::Off,
+        }
+    }
+}
+
+impl Config {
+    /// Maximum measurement time from datasheet appendix B, in microseconds
+    fn measurement_time_us(&self, chip: Chip) -> u64 {
+        let mut time = 1250 + 2300 * self.temperature.samples();
+        if self.pressure != Oversampling::Skip {
+            time += 2300 * self.pressure.samples() + 575;
+        }
+        if chip == Chip::Bme280 && self.humidity != Oversampling::Skip {
+            time += 2300 * self.humidity.samples() + 575;
+        }
+        time as u64
+    }
+}
+
+pub struct Bme280 {
+    i2c: Device,
+    address: u8,
+    chip: Chip,
+    config: Config,
+    calibration: Calibration,
+}
+
+impl Bme280 {
+    async fn read_registers(&mut self, register: u8, buffer: &mut [u8]) -> Result<(), Bme280Error> {
+        self.i2c
+            .write_read(self.address, &[register], buffer)
+            .await
+            .map_err(|_| Bme280Error::I2c)
+    }
+
+    async fn write_register(&mut self, register: u8, value: u8) -> Result<(), Bme280Error> {
+        self.i2c
+            .write(self.address, &[register, value])
+            .await
+            .map_err(|_| Bme280Error::I2c)
+    }
+
+    async fn read_register(&mut self, register: u8) -> Result<u8, Bme280Error> {
+        let mut value = [0];
+        self.read_registers(register, &mut value).await?;
+        Ok(value[0])
+    }
+
+    /// Probes both possible addresses and identifies the chip
+    pub async fn detect(mut i2c: Device, config: Config) -> Result<Self, Bme280Error> {
+        let mut result = Err(Bme280Error::I2c);
+        for address in ADDRESSES {
+            let mut id = [0];
+            result = match i2c.write_read(address, &[REG_CHIP_ID], &mut id).await {
+                Ok(_) if id[0] == CHIP_ID_BME280 => Ok((address, Chip::Bme280)),
+                Ok(_) if CHIP_IDS_BMP280.contains(&id[0]) => Ok((address, Chip::Bmp280)),
+                Ok(_) => Err(Bme280Error::UnknownChip(id[0])),
+                Err(_) => Err(Bme280Error::I2c),
+            };
+            if result.is_ok() {
+                break;
+            }
+        }
+        let (address, chip) = result?;
+
+        let mut sensor = Self {
+            i2c,
+            address,
+            chip,
+            config,
+            calibration: Calibration::default(),
+        };
+        sensor.configure().await?;
+
+        Ok(sensor)
+    }
+
+    async fn configure(&mut self) -> Result<(), Bme280Error> {
+        self.write_register(REG_RESET, RESET_COMMAND).await?;
+        Timer::after(Duration::from_millis(2)).await;
+
+        // Wait for the calibration data to be copied from NVM
+        let mut attempts = 10;
+        while self.read_register(REG_STATUS).await? & STATUS_IM_UPDATE != 0 {
+            attempts -= 1;
+            if attempts == 0 {
+                return Err(Bme280Error::NotReady);
+            }
+            Timer::after(Duration::from_millis(1)).await;
+        }
+
+        let mut tp_block = [0; 26];
+        self.read_registers(REG_CALIB_TP, &mut tp_block).await?;
+        self.calibration = Calibration::from_tp_block(&tp_block);
+
+        if self.chip == Chip::Bme280 {
+            let mut h_block = [0; 7];
+            self.read_registers(REG_CALIB_H, &mut h_block).await?;
+            self.calibration = self.calibration.with_humidity_block(&h_block);
+            self.write_register(REG_CTRL_HUM, self.config.humidity as u8)
+                .await?;
+        }
+
+        // Standby time is irrelevant in forced mode
+        self.write_register(REG_CONFIG, (self.config.filter as u8) << 2)
+            .await
+    }
+
+    /// Triggers a forced-mode conversion and returns the raw (T, P, H) values
+    async fn convert(&mut self) -> Result<(i32, i32, i32), Bme280Error> {
+        let ctrl_meas =
+            (self.config.temperature as u8) << 5 | (self.config.pressure as u8) << 2 | MODE_FORCED;
+        self.write_register(REG_CTRL_MEAS, ctrl_meas).await?;
+
+        Timer::after(Duration::from_micros(
+            self.config.measurement_time_us(self.chip),
+        ))
+        .await;
+        while self.read_register(REG_STATUS).await? & STATUS_MEASURING != 0 {
+            Timer::after(Duration::from_millis(1)).await;
+        }
+
+        let mut data = [0; 8];
+        let len = match self.chip {
+            Chip::Bme280 => 8,
+            Chip::Bmp280 => 6,
+        };
+        self.read_registers(REG_DATA, &mut data[..len]).await?;
+
+        let adc_p = (data[0] as i32) << 12 | (data[1] as i32) << 4 | (data[2] as i32) >> 4;
+        let adc_t = (data[3] as i32) << 12 | (data[4] as i32) << 4 | (data[5] as i32) >> 4;
+        let adc_h = (data[6] as i32) << 8 | data[7] as i32;
+
+        Ok((adc_t, adc_p, adc_h))
+    }
+}
+
+impl Sensor for Bme280 {
+    fn name(&self) -> &'static str {
+        match self.chip {
+            Chip::Bme280 => "bme280",
+            Chip::Bmp280 => "bmp280",
+        }
+    }
+
+    async fn measure(&mut self) -> Result<Measurements, SensorError> {
+        let (adc_t, adc_p, adc_h) = self.convert().await.map_err(|e| {
+            warn!("BME280 conversion failed: {}", e);
+            SensorError::ReadFailed
+        })?;
+
+        let (temperature, t_fine) = self.calibration.temperature(adc_t);
+
+        let mut measurements = Measurements::new();
+        // Cannot fail - fewer measurements than MAX_MEASUREMENTS
+        let _ = measurements.push(Measurement::new(
+            Quantity::Temperature,
+            Unit::Celsius,
+            temperature,
+            2,
+        ));
+        if self.config.pressure != Oversampling::Skip {
+            // Pa in Q24.8 - whole pascals are hectopascals with two decimals
+            let pressure = self.calibration.pressure(adc_p, t_fine) >> 8;
+            let _ = measurements.push(Measurement::new(
+                Quantity::Pressure,
+                Unit::Hectopascal,
+                pressure as i32,
+                2,
+            ));
+        }
+        if self.chip == Chip::Bme280 && self.config.humidity != Oversampling::Skip {
+            // %RH in Q22.10, rounded to tenths
+            let humidity = (self.calibration.humidity(adc_h, t_fine) * 10 + 512) >> 10;
+            let _ = measurements.push(Measurement::new(
+                Quantity::RelativeHumidity,
+                Unit::Percent,
+                humidity as i32,
+                1,
+            ));
+        }
+
+        Ok(measurements)
+    }
+}
+
+pub async fn init(i2c: Device, config: Config) {
+    match Bme280::detect(i2c, config).await {
+        Ok(sensor) => {
+            info!("Found {} at {:#x}", sensor.chip, sensor.address);
+            super::sensor::register(AnySensor::Bme280(sensor)).await;
+        }
+        Err(e) => warn!("No BME280/BMP280 found: {}", e),
+    }
+}
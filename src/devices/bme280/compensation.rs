@@ -0,0 +1,198 @@
+//! Integer compensation formulas from the Bosch BME280 datasheet (rev. 1.6,
+//! section 4.2.3), also valid for the BMP280 temperature and pressure.
+
+#[derive(Default, Clone, Copy)]
+pub struct Calibration {
+    pub t1: u16,
+    pub t2: i16,
+    pub t3: i16,
+    pub p1: u16,
+    pub p2: i16,
+    pub p3: i16,
+    pub p4: i16,
+    pub p5: i16,
+    pub p6: i16,
+    pub p7: i16,
+    pub p8: i16,
+    pub p9: i16,
+    pub h1: u8,
+    pub h2: i16,
+    pub h3: u8,
+    pub h4: i16,
+    pub h5: i16,
+    pub h6: i8,
+}
+
+impl Calibration {
+    /// Parses the temperature and pressure block at 0x88..=0xA1
+    pub fn from_tp_block(block: &[u8; 26]) -> Self {
+        let u16_at = |i: usize| u16::from_le_bytes([block[i], block[i + 1]]);
+        let i16_at = |i: usize| i16::from_le_bytes([block[i], block[i + 1]]);
+
+        Self {
+            t1: u16_at(0),
+            t2: i16_at(2),
+            t3: i16_at(4),
+            p1: u16_at(6),
+            p2: i16_at(8),
+            p3: i16_at(10),
+            p4: i16_at(12),
+            p5: i16_at(14),
+            p6: i16_at(16),
+            p7: i16_at(18),
+            p8: i16_at(20),
+            p9: i16_at(22),
+            h1: block[25],
+            ..Default::default()
+        }
+    }
+
+    /// Adds the humidity block at 0xE1..=0xE7 (BME280 only)
+    pub fn with_humidity_block(self, block: &[u8; 7]) -> Self {
+        Self {
+            h2: i16::from_le_bytes([block[0], block[1]]),
+            h3: block[2],
+            h4: ((block[3] as i8 as i16) << 4) | (block[4] & 0x0f) as i16,
+            h5: ((block[5] as i8 as i16) << 4) | (block[4] >> 4) as i16,
+            h6: block[6] as i8,
+            ..self
+        }
+    }
+
+    /// Returns the temperature in 0.01 °C and the `t_fine` value needed by
+    /// the pressure and humidity compensation
+    pub fn temperature(&self, adc_t: i32) -> (i32, i32) {
+        let t1 = self.t1 as i32;
+        let var1 = (((adc_t >> 3) - (t1 << 1)) * self.t2 as i32) >> 11;
+        let var2 = (((((adc_t >> 4) - t1) * ((adc_t >> 4) - t1)) >> 12) * self.t3 as i32) >> 14;
+        let t_fine = var1 + var2;
+
+        ((t_fine * 5 + 128) >> 8, t_fine)
+    }
+
+    /// Returns the pressure in Pa as Q24.8 fixed point
+    pub fn pressure(&self, adc_p: i32, t_fine: i32) -> u32 {
+        let mut var1 = t_fine as i64 - 128000;
+        let mut var2 = var1 * var1 * self.p6 as i64;
+        var2 += (var1 * self.p5 as i64) << 17;
+        var2 += (self.p4 as i64) << 35;
+        var1 = ((var1 * var1 * self.p3 as i64) >> 8) + ((var1 * self.p2 as i64) << 12);
+        var1 = (((1i64 << 47) + var1) * self.p1 as i64) >> 33;
+        if var1 == 0 {
+            // Avoid division by zero on a missing calibration
+            return 0;
+        }
+
+        let mut p = 1048576 - adc_p as i64;
+        p = (((p << 31) - var2) * 3125) / var1;
+        let var1 = (self.p9 as i64 * (p >> 13) * (p >> 13)) >> 25;
+        let var2 = (self.p8 as i64 * p) >> 19;
+
+        (((p + var1 + var2) >> 8) + ((self.p7 as i64) << 4)) as u32
+    }
+
+    /// Returns the relative humidity in % as Q22.10 fixed point
+    pub fn humidity(&self, adc_h: i32, t_fine: i32) -> u32 {
+        let v = t_fine - 76800;
+        let v = (((adc_h << 14) - ((self.h4 as i32) << 20) - (self.h5 as i32 * v) + 16384) >> 15)
+            * (((((((v * self.h6 as i32) >> 10) * (((v * self.h3 as i32) >> 11) + 32768)) >> 10)
+                + 2097152)
+                * self.h2 as i32
+                + 8192)
+                >> 14);
+        let v = v - (((((v >> 15) * (v >> 15)) >> 7) * self.h1 as i32) >> 4);
+        let v = v.clamp(0, 419430400);
+
+        (v >> 12) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The calibration words of the BMP280 datasheet's worked example, with
+    /// the humidity ones of a sample BME280
+    fn example() -> Calibration {
+        let words: [u16; 12] = [
+            27504,
+            26435,
+            -1000i16 as u16,
+            36477,
+            -10685i16 as u16,
+            3024,
+            2855,
+            140,
+            -7i16 as u16,
+            15500,
+            -14600i16 as u16,
+            6000,
+        ];
+        let mut block = [0; 26];
+        for (i, word) in words.iter().enumerate() {
+            block[2 * i..2 * i + 2].copy_from_slice(&word.to_le_bytes());
+        }
+        block[25] = 75;
+
+        // H4 = 313 and H5 = 50 share the middle byte
+        Calibration::from_tp_block(&block)
+            .with_humidity_block(&[0x6a, 0x01, 0, 0x13, 0x29, 0x03, 30])
+    }
+
+    #[test]
+    fn parses_calibration_blocks() {
+        let calibration = example();
+        assert_eq!(calibration.t1, 27504);
+        assert_eq!(calibration.t3, -1000);
+        assert_eq!(calibration.p9, 6000);
+        assert_eq!(calibration.h1, 75);
+        assert_eq!(calibration.h2, 362);
+        assert_eq!(calibration.h4, 313);
+        assert_eq!(calibration.h5, 50);
+        assert_eq!(calibration.h6, 30);
+    }
+
+    #[test]
+    fn temperature_matches_datasheet() {
+        let (temperature, t_fine) = example().temperature(519888);
+        assert_eq!(temperature, 2508);
+        assert_eq!(t_fine, 128422);
+    }
+
+    #[test]
+    fn pressure_matches_datasheet() {
+        let calibration = example();
+        let (_, t_fine) = calibration.temperature(519888);
+        let pressure = calibration.pressure(415148, t_fine) as f64 / 256.0;
+        assert!((pressure - 100653.27).abs() < 0.05, "{} Pa", pressure);
+    }
+
+    /// The datasheet has no example for humidity, so the integer formula is
+    /// checked against its floating point one
+    #[test]
+    fn humidity_matches_floating_point_formula() {
+        let calibration = example();
+        let (_, t_fine) = calibration.temperature(519888);
+
+        for adc_h in [20000, 27000, 32000, 40000] {
+            let h = t_fine as f64 - 76800.0;
+            let h = (adc_h as f64
+                - (calibration.h4 as f64 * 64.0 + calibration.h5 as f64 / 16384.0 * h))
+                * (calibration.h2 as f64 / 65536.0
+                    * (1.0
+                        + calibration.h6 as f64 / 67108864.0
+                            * h
+                            * (1.0 + calibration.h3 as f64 / 67108864.0 * h)));
+            let expected = (h * (1.0 - calibration.h1 as f64 * h / 524288.0)).clamp(0.0, 100.0);
+
+            let humidity = calibration.humidity(adc_h, t_fine) as f64 / 1024.0;
+            assert!(
+                (humidity - expected).abs() < 0.01,
+                "{} %RH instead of {} for {}",
+                humidity,
+                expected,
+                adc_h
+            );
+        }
+    }
+}
@@ -1,21 +1,261 @@
-use embassy_dht::dht11::DHT11;
-use embassy_rp::gpio::AnyPin;
-use embassy_sync::{blocking_mutex::raw::ThreadModeRawMutex, mutex::Mutex};
-use embassy_time::Delay;
+use core::ops::RangeInclusive;
 
-static DHT: Mutex<ThreadModeRawMutex, Option<DHT11<'_, Delay>>> = Mutex::new(None);
+use defmt::{debug, Format};
+use embassy_rp::gpio::{AnyPin, Flex, Pull};
+use embassy_time::{Duration, Instant, Timer};
 
-pub async fn init(pin: AnyPin) {
-    let dht = DHT11::new(pin, Delay);
+use super::sensor::{AnySensor, Measurement, Measurements, Quantity, Sensor, SensorError, Unit};
 
-    *DHT.lock().await = Some(dht);
+/// Longest time the line may stay in one state during a transmission
+const BIT_TIMEOUT_US: u64 = 100;
+/// High pulses longer than this encode a 1 (26-28 us for 0, 70 us for 1)
+const ONE_THRESHOLD_US: u64 = 48;
+
+const MAX_ATTEMPTS: usize = 3;
+/// Largest change (tenths) accepted between readings less than
+/// `JUMP_WINDOW` apart
+const MAX_TEMPERATURE_JUMP: i32 = 50;
+const MAX_HUMIDITY_JUMP: i32 = 200;
+const JUMP_WINDOW: Duration = Duration::from_secs(5 * 60);
+/// Consecutive rejected jumps after which the new level is believed
+const MAX_REJECTIONS: u8 = 3;
+
+#[derive(Format, Clone, Copy, PartialEq, Eq)]
+pub enum Model {
+    /// 0..50 °C, 20..90 %RH, integer resolution on most parts
+    Dht11,
+    /// DHT22/AM2302: -40..80 °C, 0..100 %RH, 0.1 resolution
+    Dht22,
+}
+
+impl Model {
+    /// Shortest time between two reads the sensor tolerates
+    fn min_interval(&self) -> Duration {
+        match self {
+            Model::Dht11 => Duration::from_secs(1),
+            Model::Dht22 => Duration::from_secs(2),
+        }
+    }
+
+    /// Measuring ranges (tenths of °C, tenths of %RH)
+    fn ranges(&self) -> (RangeInclusive<i32>, RangeInclusive<i32>) {
+        match self {
+            Model::Dht11 => (0..=500, 200..=900),
+            Model::Dht22 => (-400..=800, 0..=1000),
+        }
+    }
+
+    fn start_signal(&self) -> Duration {
+        match self {
+            Model::Dht11 => Duration::from_millis(20),
+            Model::Dht22 => Duration::from_millis(2),
+        }
+    }
+
+    /// Decodes a checksummed frame into (temperature, humidity) in tenths
+    fn decode(&self, frame: &[u8; 5]) -> (i32, i32) {
+        match self {
+            Model::Dht11 => {
+                let humidity = frame[0] as i32 * 10 + frame[1] as i32;
+                let temperature = frame[2] as i32 * 10 + (frame[3] & 0x7f) as i32;
+                if frame[3] & 0x80 != 0 {
+                    (-temperature, humidity)
+                } else {
+                    (temperature, humidity)
+                }
+            }
+            Model::Dht22 => {
+                let humidity = u16::from_be_bytes([frame[0], frame[1]]) as i32;
+                let temperature = u16::from_be_bytes([frame[2] & 0x7f, frame[3]]) as i32;
+                if frame[2] & 0x80 != 0 {
+                    (-temperature, humidity)
+                } else {
+                    (temperature, humidity)
+                }
+            }
+        }
+    }
+}
+
+#[derive(Format, Clone, Copy, PartialEq, Eq)]
+pub enum DhtError {
+    /// The sensor didn't answer the start signal - disconnected or dead
+    NoResponse,
+    /// The transmission stopped midway - usually a bad wire or contact
+    Timeout,
+    Checksum,
+    /// Out of the sensor's range, or an unrealistic jump from the last reading
+    Implausible,
+}
+
+impl From<DhtError> for SensorError {
+    fn from(e: DhtError) -> Self {
+        match e {
+            DhtError::NoResponse => SensorError::NoResponse,
+            DhtError::Timeout => SensorError::Timeout,
+            DhtError::Checksum => SensorError::Checksum,
+            DhtError::Implausible => SensorError::Implausible,
+        }
+    }
+}
+
+pub struct DhtSensor {
+    pin: Flex<'static>,
+    model: Model,
+    last_read: Option<Instant>,
+    /// Last accepted reading with its time
+    last_good: Option<(Instant, i32, i32)>,
+    rejections: u8,
+}
+
+impl DhtSensor {
+    pub fn new(pin: AnyPin, model: Model) -> Self {
+        let mut pin = Flex::new(pin);
+        pin.set_pull(Pull::Up);
+        pin.set_as_input();
+
+        Self {
+            pin,
+            model,
+            last_read: None,
+            last_good: None,
+            rejections: 0,
+        }
+    }
+
+    /// Busy-waits while the line is at `high`, returning how long that took
+    fn wait_while(&self, high: bool) -> Result<u64, DhtError> {
+        let start = Instant::now();
+        while self.pin.is_high() == high {
+            if start.elapsed().as_micros() > BIT_TIMEOUT_US {
+                return Err(DhtError::Timeout);
+            }
+        }
+        Ok(start.elapsed().as_micros())
+    }
+
+    /// Times the next low/high pulse pair, returning the high width.
+    /// Interrupts (WiFi) are held off only for the pair, pending ones run
+    /// at the start of the following 50 us low phase.
+    fn pulse(&self) -> Result<u64, DhtError> {
+        critical_section::with(|_| {
+            self.wait_while(false)?;
+            self.wait_while(true)
+        })
+    }
+
+    fn receive_frame(&self) -> Result<[u8; 5], DhtError> {
+        // Response: sensor pulls low for 80 us, then high for 80 us
+        critical_section::with(|_| self.wait_while(true)).map_err(|_| DhtError::NoResponse)?;
+        self.pulse()?;
+
+        let mut frame = [0u8; 5];
+        for bit in 0..40 {
+            if self.pulse()? > ONE_THRESHOLD_US {
+                frame[bit / 8] |= 0x80 >> (bit % 8);
+            }
+        }
+
+        Ok(frame)
+    }
+
+    async fn read_once(&mut self) -> Result<(i32, i32), DhtError> {
+        if let Some(last_read) = self.last_read {
+            let since = last_read.elapsed();
+            if since < self.model.min_interval() {
+                Timer::after(self.model.min_interval() - since).await;
+            }
+        }
+        self.last_read = Some(Instant::now());
+
+        self.pin.set_low();
+        self.pin.set_as_output();
+        Timer::after(self.model.start_signal()).await;
+
+        self.pin.set_as_input();
+        let frame = self.receive_frame()?;
+
+        let checksum = frame[..4].iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        if checksum != frame[4] {
+            return Err(DhtError::Checksum);
+        }
+
+        Ok(self.model.decode(&frame))
+    }
+
+    fn check_plausible(&mut self, temperature: i32, humidity: i32) -> Result<(), DhtError> {
+        let (temperature_range, humidity_range) = self.model.ranges();
+        if !temperature_range.contains(&temperature) || !humidity_range.contains(&humidity) {
+            return Err(DhtError::Implausible);
+        }
+
+        if let Some((time, last_temperature, last_humidity)) = self.last_good {
+            let jumped = (temperature - last_temperature).abs() > MAX_TEMPERATURE_JUMP
+                || (humidity - last_humidity).abs() > MAX_HUMIDITY_JUMP;
+            if jumped && time.elapsed() < JUMP_WINDOW && self.rejections < MAX_REJECTIONS {
+                self.rejections += 1;
+                return Err(DhtError::Implausible);
+            }
+        }
+
+        self.rejections = 0;
+        self.last_good = Some((Instant::now(), temperature, humidity));
+        Ok(())
+    }
+
+    /// Reads (temperature, humidity) in tenths, retrying failed or
+    /// implausible readings while respecting the sensor's minimum interval
+    pub async fn read(&mut self) -> Result<(i32, i32), DhtError> {
+        let mut result = Err(DhtError::NoResponse);
+
+        for _ in 0..MAX_ATTEMPTS {
+            result = match self.read_once().await {
+                Ok((temperature, humidity)) => self
+                    .check_plausible(temperature, humidity)
+                    .map(|_| (temperature, humidity)),
+                Err(e) => Err(e),
+            };
+
+            match result {
+                Ok(_) => break,
+                Err(e) => debug!("{} read attempt failed: {}", self.name(), e),
+            }
+        }
+
+        result
+    }
 }
 
-pub async fn read() -> Option<embassy_dht::Reading<i8, u8>> {
-    let mut dht = DHT.lock().await;
+impl Sensor for DhtSensor {
+    fn name(&self) -> &'static str {
+        match self.model {
+            Model::Dht11 => "dht11",
+            Model::Dht22 => "dht22",
+        }
+    }
+
+    async fn measure(&mut self) -> Result<Measurements, SensorError> {
+        let (temperature, humidity) = self.read().await?;
 
-    match dht.as_mut() {
-        Some(dht) => dht.read().ok(),
-        None => None,
+        let mut measurements = Measurements::new();
+        // Cannot fail - fewer measurements than MAX_MEASUREMENTS
+        let _ = measurements.push(Measurement::new(
+            Quantity::Temperature,
+            Unit::Celsius,
+            temperature,
+            1,
+        ));
+        let _ = measurements.push(Measurement::new(
+            Quantity::RelativeHumidity,
+            Unit::Percent,
+            humidity,
+            1,
+        ));
+
+        Ok(measurements)
     }
 }
+
+pub async fn init(pin: AnyPin, model: Model) {
+    super::sensor::register(AnySensor::Dht(DhtSensor::new(pin, model))).await;
+}
@@ -0,0 +1,135 @@
+use core::cell::RefCell;
+
+use embassy_rp::{
+    flash::{Blocking, Error, Flash, ERASE_SIZE, READ_SIZE, WRITE_SIZE},
+    peripherals::FLASH,
+};
+use embassy_sync::blocking_mutex::{raw::ThreadModeRawMutex, Mutex};
+use embedded_storage::nor_flash::{ErrorType, NorFlash, ReadNorFlash};
+
+pub const FLASH_SIZE: usize = 2 * 1024 * 1024;
+
+/// Start of the areas left out of the program's FLASH region in memory.x,
+/// as offsets from the start of flash
+const LOG_START: u32 = 0x18_0000;
+const STORAGE_START: u32 = FLASH_SIZE as u32 - 64 * 1024;
+
+/// One sector holding the sensor calibrations
+pub const CALIBRATION: u32 = STORAGE_START;
+/// One sector holding the station settings
+pub const CONFIG: u32 = STORAGE_START + ERASE_SIZE as u32;
+
+/// Erasing and writing stall execution from flash anyway, so the flash is
+/// used blocking, like the ADC
+static FLASH: Mutex<
+    ThreadModeRawMutex,
+    RefCell<Option<Flash<'static, FLASH, Blocking, FLASH_SIZE>>>,
+> = Mutex::new(RefCell::new(None));
+
+pub fn init(flash: FLASH) {
+    FLASH.lock(|cell| cell.replace(Some(Flash::new_blocking(flash))));
+}
+
+pub fn read(offset: u32, bytes: &mut [u8]) -> Result<(), Error> {
+    FLASH.lock(|cell| {
+        let mut flash = cell.borrow_mut();
+        let flash = flash.as_mut().ok_or(Error::Other)?;
+        flash.blocking_read(offset, bytes)
+    })
+}
+
+/// Erases the sector at `offset` and writes `bytes` to its start
+pub fn write_sector(offset: u32, bytes: &[u8]) -> Result<(), Error> {
+    if bytes.len() > ERASE_SIZE {
+        return Err(Error::OutOfBounds);
+    }
+
+    FLASH.lock(|cell| {
+        let mut flash = cell.borrow_mut();
+        let flash = flash.as_mut().ok_or(Error::Other)?;
+        flash.blocking_erase(offset, offset + ERASE_SIZE as u32)?;
+        flash.blocking_write(offset, bytes)
+    })
+}
+
+/// A region of flash for the embedded-storage traits, with offsets relative
+/// to its start
+pub struct Partition {
+    offset: u32,
+    size: u32,
+}
+
+/// The log area's sectors given to the raw samples and to the hourly and
+/// daily aggregates. A sector holds 255 raw or 127 aggregate records and
+/// one sector of each log is always erased, so with the 16 measurements
+/// logged every five minutes they keep about 41 hours, 15 days and 8 months.
+const RAW_SECTORS: u32 = 32;
+const HOURLY_SECTORS: u32 = 48;
+const DAILY_SECTORS: u32 = (STORAGE_START - LOG_START) / SECTOR - RAW_SECTORS - HOURLY_SECTORS;
+const SECTOR: u32 = ERASE_SIZE as u32;
+
+impl Partition {
+    /// The measurement history at full resolution
+    pub const RAW: Self = Self {
+        offset: LOG_START,
+        size: RAW_SECTORS * SECTOR,
+    };
+    /// Hourly aggregates of the measurement history
+    pub const HOURLY: Self = Self {
+        offset: LOG_START + RAW_SECTORS * SECTOR,
+        size: HOURLY_SECTORS * SECTOR,
+    };
+    /// Daily aggregates of the measurement history
+    pub const DAILY: Self = Self {
+        offset: LOG_START + (RAW_SECTORS + HOURLY_SECTORS) * SECTOR,
+        size: DAILY_SECTORS * SECTOR,
+    };
+
+    fn check(&self, offset: u32, length: usize) -> Result<u32, Error> {
+        match offset.checked_add(length as u32) {
+            Some(end) if end <= self.size => Ok(self.offset + offset),
+            _ => Err(Error::OutOfBounds),
+        }
+    }
+}
+
+impl ErrorType for Partition {
+    type Error = Error;
+}
+
+impl ReadNorFlash for Partition {
+    const READ_SIZE: usize = READ_SIZE;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Error> {
+        read(self.check(offset, bytes.len())?, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        self.size as usize
+    }
+}
+
+impl NorFlash for Partition {
+    const WRITE_SIZE: usize = WRITE_SIZE;
+    const ERASE_SIZE: usize = ERASE_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Error> {
+        let from = self.check(from, 0)?;
+        let to = self.check(to, 0)?;
+        FLASH.lock(|cell| {
+            let mut flash = cell.borrow_mut();
+            flash.as_mut().ok_or(Error::Other)?.blocking_erase(from, to)
+        })
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Error> {
+        let offset = self.check(offset, bytes.len())?;
+        FLASH.lock(|cell| {
+            let mut flash = cell.borrow_mut();
+            flash
+                .as_mut()
+                .ok_or(Error::Other)?
+                .blocking_write(offset, bytes)
+        })
+    }
+}
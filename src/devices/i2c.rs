@@ -0,0 +1,21 @@
+use embassy_embedded_hal::shared_bus::asynch::i2c::I2cDevice;
+use embassy_rp::{
+    i2c::{Async, I2c},
+    peripherals::I2C0,
+};
+use embassy_sync::{blocking_mutex::raw::ThreadModeRawMutex, mutex::Mutex};
+use static_cell::StaticCell;
+
+pub type Bus = I2c<'static, I2C0, Async>;
+
+/// Handle to one device on the shared I2C bus
+pub type Device = I2cDevice<'static, ThreadModeRawMutex, Bus>;
+
+static BUS: StaticCell<Mutex<ThreadModeRawMutex, Bus>> = StaticCell::new();
+
+/// Shares the bus between drivers, returns a function creating device handles
+pub fn init(i2c: Bus) -> impl Fn() -> Device {
+    let bus: &'static _ = BUS.init(Mutex::new(i2c));
+
+    move || I2cDevice::new(bus)
+}
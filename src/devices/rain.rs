@@ -0,0 +1,204 @@
+use core::{mem::MaybeUninit, ptr::addr_of_mut};
+
+use defmt::info;
+use embassy_rp::gpio::{AnyPin, Input, Pull};
+use embassy_sync::{blocking_mutex::raw::ThreadModeRawMutex, mutex::Mutex};
+use embassy_time::{Duration, Timer};
+
+use super::{
+    rtc,
+    sensor::{AnySensor, Measurement, Measurements, Quantity, Sensor, SensorError, Unit},
+};
+use crate::config;
+
+/// Reed switch contact bounce
+const DEBOUNCE: Duration = Duration::from_millis(50);
+const MINUTES_PER_DAY: u32 = 24 * 60;
+/// Minutes of tips the rain rate is averaged over
+const RATE_WINDOW: u32 = 10;
+const MAGIC: u32 = 0x5241_494e;
+
+/// Tip counts, kept in RAM which is not cleared on reset so that a soft
+/// reset or watchdog reboot doesn't lose the day's rain
+#[repr(C)]
+struct Counts {
+    magic: u32,
+    /// Minutes since the epoch of the newest bin
+    newest: u32,
+    total: u32,
+    /// Tips per minute over the last 24 h, indexed by minute of the day
+    bins: [u16; MINUTES_PER_DAY as usize],
+    checksum: u32,
+}
+
+impl Counts {
+    fn compute_checksum(&self) -> u32 {
+        let header = [self.magic, self.newest, self.total];
+        let bins = self.bins.iter().map(|b| *b as u32);
+        header
+            .into_iter()
+            .chain(bins)
+            .fold(0x811c_9dc5, |hash, word| {
+                (hash ^ word).wrapping_mul(0x0100_0193)
+            })
+    }
+
+    fn is_valid(&self) -> bool {
+        self.magic == MAGIC && self.checksum == self.compute_checksum()
+    }
+
+    fn seal(&mut self) {
+        self.checksum = self.compute_checksum();
+    }
+
+    fn reset(&mut self, minute: u32) {
+        self.magic = MAGIC;
+        self.newest = minute;
+        self.total = 0;
+        self.bins.fill(0);
+        self.seal();
+    }
+
+    fn bin(&mut self, minute: u32) -> &mut u16 {
+        &mut self.bins[(minute % MINUTES_PER_DAY) as usize]
+    }
+
+    /// Moves the newest bin to `minute`, clearing the bins skipped over
+    fn advance(&mut self, minute: u32) {
+        if minute < self.newest {
+            // The clock was set back, bins can't be placed in time anymore
+            let total = self.total;
+            self.reset(minute);
+            self.total = total;
+        } else {
+            let skipped = (minute - self.newest).min(MINUTES_PER_DAY);
+            for m in (minute - skipped + 1)..=minute {
+                *self.bin(m) = 0;
+            }
+            self.newest = minute;
+        }
+        self.seal();
+    }
+
+    /// Tips in the last `minutes` minutes, including the current one
+    fn sum(&self, minutes: u32) -> u32 {
+        (0..minutes.min(MINUTES_PER_DAY))
+            .map(|i| {
+                let minute = self.newest.wrapping_sub(i) % MINUTES_PER_DAY;
+                self.bins[minute as usize] as u32
+            })
+            .sum()
+    }
+}
+
+#[link_section = ".uninit.rain"]
+static mut PERSISTENT: MaybeUninit<Counts> = MaybeUninit::uninit();
+
+static COUNTS: Mutex<ThreadModeRawMutex, Option<&'static mut Counts>> = Mutex::new(None);
+
+/// Accumulated rain in µm, rate in µm/h
+#[derive(Clone, Copy)]
+pub struct Accumulation {
+    pub last_hour: u32,
+    pub last_day: u32,
+    pub since_midnight: u32,
+    pub rate: u32,
+    pub total: u32,
+}
+
+async fn current_minute() -> Option<u32> {
+    rtc::now().await.map(|dt| rtc::to_timestamp(&dt) / 60)
+}
+
+async fn record_tip() {
+    let Some(minute) = current_minute().await else {
+        return;
+    };
+
+    if let Some(counts) = COUNTS.lock().await.as_mut() {
+        counts.advance(minute);
+        let bin = counts.bin(minute);
+        *bin = bin.saturating_add(1);
+        counts.total = counts.total.wrapping_add(1);
+        counts.seal();
+    }
+}
+
+pub async fn accumulation() -> Option<Accumulation> {
+    let minute = current_minute().await?;
+    let per_tip = config::get().await.rain_per_tip as u32;
+
+    let mut counts = COUNTS.lock().await;
+    let counts = counts.as_mut()?;
+    counts.advance(minute);
+
+    Some(Accumulation {
+        last_hour: counts.sum(60) * per_tip,
+        last_day: counts.sum(MINUTES_PER_DAY) * per_tip,
+        since_midnight: counts.sum(minute % MINUTES_PER_DAY + 1) * per_tip,
+        rate: counts.sum(RATE_WINDOW) * per_tip * (60 / RATE_WINDOW),
+        total: counts.total * per_tip,
+    })
+}
+
+/// Restores the counts kept over a soft reset, call once before [`run`]
+pub async fn init() {
+    // SAFETY: this is the only place the static is accessed, and it is only
+    // called once. Counts is all integers, so leftover RAM contents are a
+    // valid value, which the checksum then accepts or rejects.
+    let counts = unsafe { (*addr_of_mut!(PERSISTENT)).assume_init_mut() };
+
+    if counts.is_valid() {
+        info!("Restored {} rain gauge tips", counts.total);
+    } else {
+        counts.reset(current_minute().await.unwrap_or(0));
+    }
+
+    *COUNTS.lock().await = Some(counts);
+    super::sensor::register(AnySensor::Rain(RainGauge)).await;
+}
+
+/// Counts bucket tips of a reed switch pulling `pin` low
+pub async fn run(pin: AnyPin) -> ! {
+    let mut input = Input::new(pin, Pull::Up);
+
+    loop {
+        input.wait_for_falling_edge().await;
+        record_tip().await;
+
+        Timer::after(DEBOUNCE).await;
+        input.wait_for_high().await;
+        Timer::after(DEBOUNCE).await;
+    }
+}
+
+/// Registry entry reporting the accumulated counts
+pub struct RainGauge;
+
+impl Sensor for RainGauge {
+    fn name(&self) -> &'static str {
+        "rain"
+    }
+
+    async fn measure(&mut self) -> Result<Measurements, SensorError> {
+        let accumulation = accumulation().await.ok_or(SensorError::ReadFailed)?;
+
+        let mut measurements = Measurements::new();
+        // Cannot fail - fewer measurements than MAX_MEASUREMENTS
+        // µm are mm with three decimals, keep two
+        let _ = measurements.push(Measurement::new(
+            Quantity::Precipitation,
+            Unit::Millimetre,
+            (accumulation.since_midnight / 10) as i32,
+            2,
+        ));
+        let _ = measurements.push(Measurement::new(
+            Quantity::RainRate,
+            Unit::MillimetrePerHour,
+            (accumulation.rate / 10) as i32,
+            2,
+        ));
+
+        Ok(measurements)
+    }
+}
@@ -39,4 +39,115 @@ pub async fn set_time(t: DateTime) -> Result<(), ()> {
     rtc.set_datetime(t).map_err(|_| ())?;
     
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Seconds since 1970-01-01 00:00:00 in the RTC's (local) time
+pub type Timestamp = u32;
+
+/// Days since the epoch, counting years from March so that the leap day is last
+fn days_from_civil(year: u32, month: u32, day: u32) -> u32 {
+    let (year, month) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+pub fn to_timestamp(dt: &DateTime) -> Timestamp {
+    let days = days_from_civil(dt.year as u32, dt.month as u32, dt.day as u32);
+    days * 86400 + dt.hour as u32 * 3600 + dt.minute as u32 * 60 + dt.second as u32
+}
+
+/// Inverse of [`to_timestamp`]
+pub fn from_timestamp(timestamp: Timestamp) -> DateTime {
+    let days = timestamp / 86400;
+    let seconds = timestamp % 86400;
+
+    let day_of_era_base = days + 719468;
+    let era = day_of_era_base / 146097;
+    let day_of_era = day_of_era_base - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as u32;
+
+    // 1970-01-01 was a Thursday
+    let day_of_week = match (days + 4) % 7 {
+        0 => DayOfWeek::Sunday,
+        1 => DayOfWeek::Monday,
+        2 => DayOfWeek::Tuesday,
+        3 => DayOfWeek::Wednesday,
+        4 => DayOfWeek::Thursday,
+        5 => DayOfWeek::Friday,
+        _ => DayOfWeek::Saturday,
+    };
+
+    DateTime {
+        year: year as u16,
+        month: month as u8,
+        day: day as u8,
+        day_of_week,
+        hour: (seconds / 3600) as u8,
+        minute: (seconds / 60 % 60) as u8,
+        second: (seconds % 60) as u8,
+    }
+}
+
+/// Years a timestamp can hold, from its epoch up to 2^32 seconds later
+const YEARS: core::ops::RangeInclusive<u32> = 1970..=2105;
+
+fn days_in_month(year: u32, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Whether a date exists and can be turned into a timestamp
+pub fn is_valid_date(year: u32, month: u32, day: u32) -> bool {
+    YEARS.contains(&year)
+        && (1..=12).contains(&month)
+        && (1..=days_in_month(year, month)).contains(&day)
+}
+
+/// Parses an ISO 8601 date, `YYYY-MM-DD`, into the timestamp of its midnight
+pub fn parse_date(date: &str) -> Option<Timestamp> {
+    let mut parts = date.splitn(3, '-');
+    let year: u32 = parts.next()?.parse().ok()?;
+    let month: u32 = parts.next()?.parse().ok()?;
+    let day: u32 = parts.next()?.parse().ok()?;
+    if !is_valid_date(year, month, day) {
+        return None;
+    }
+
+    Some(days_from_civil(year, month, day) * 86400)
+}
+
+/// Formats a timestamp as an ISO 8601 local date and time
+pub struct Iso8601(pub Timestamp);
+
+impl core::fmt::Display for Iso8601 {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let dt = from_timestamp(self.0);
+        core::write!(
+            f,
+            "{}-{:02}-{:02}T{:02}:{:02}:{:02}",
+            dt.year,
+            dt.month,
+            dt.day,
+            dt.hour,
+            dt.minute,
+            dt.second
+        )
+    }
+}
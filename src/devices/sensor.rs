@@ -0,0 +1,399 @@
+use core::{cell::RefCell, fmt};
+
+use defmt::{warn, Format};
+use embassy_sync::{
+    blocking_mutex::{self, raw::ThreadModeRawMutex},
+    mutex::Mutex,
+};
+use embassy_time::{Duration, Instant};
+use heapless::Vec;
+
+use crate::calibration;
+
+use super::{
+    bh1750::Bh1750, bme280::Bme280, dht::DhtSensor, rain::RainGauge, rtc, sht::Sht,
+    veml6075::Veml6075, wind::WindSensor,
+};
+
+pub const MAX_SENSORS: usize = 8;
+pub const MAX_MEASUREMENTS: usize = 4;
+/// Sensor and quantity pairs the registered sensors can report at most
+pub const MAX_SERIES: usize = MAX_SENSORS * MAX_MEASUREMENTS;
+
+#[derive(Format, Clone, Copy, PartialEq, Eq)]
+pub enum Quantity {
+    Temperature,
+    RelativeHumidity,
+    Pressure,
+    Precipitation,
+    RainRate,
+    WindSpeed,
+    WindGust,
+    WindDirection,
+    Illuminance,
+    UvIndex,
+    UvA,
+    UvB,
+}
+
+impl Quantity {
+    pub const ALL: [Quantity; 12] = [
+        Quantity::Temperature,
+        Quantity::RelativeHumidity,
+        Quantity::Pressure,
+        Quantity::Precipitation,
+        Quantity::RainRate,
+        Quantity::WindSpeed,
+        Quantity::WindGust,
+        Quantity::WindDirection,
+        Quantity::Illuminance,
+        Quantity::UvIndex,
+        Quantity::UvA,
+        Quantity::UvB,
+    ];
+
+    /// Short identifier for forms, APIs and storage
+    pub fn key(&self) -> &'static str {
+        match self {
+            Quantity::Temperature => "temperature",
+            Quantity::RelativeHumidity => "humidity",
+            Quantity::Pressure => "pressure",
+            Quantity::Precipitation => "precipitation",
+            Quantity::RainRate => "rain_rate",
+            Quantity::WindSpeed => "wind_speed",
+            Quantity::WindGust => "wind_gust",
+            Quantity::WindDirection => "wind_direction",
+            Quantity::Illuminance => "illuminance",
+            Quantity::UvIndex => "uv_index",
+            Quantity::UvA => "uva",
+            Quantity::UvB => "uvb",
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|q| q.key() == key)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Quantity::Temperature => "temperature",
+            Quantity::RelativeHumidity => "relative humidity",
+            Quantity::Pressure => "pressure",
+            Quantity::Precipitation => "precipitation",
+            Quantity::RainRate => "rain rate",
+            Quantity::WindSpeed => "wind speed",
+            Quantity::WindGust => "wind gust",
+            Quantity::WindDirection => "wind direction",
+            Quantity::Illuminance => "illuminance",
+            Quantity::UvIndex => "UV index",
+            Quantity::UvA => "UVA",
+            Quantity::UvB => "UVB",
+        }
+    }
+}
+
+#[derive(Format, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    Celsius,
+    Percent,
+    Hectopascal,
+    Millimetre,
+    MillimetrePerHour,
+    MetrePerSecond,
+    Degree,
+    Lux,
+    Counts,
+    /// Dimensionless quantities, like the UV index
+    None,
+}
+
+impl Unit {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Unit::Celsius => "°C",
+            Unit::Percent => "%",
+            Unit::Hectopascal => "hPa",
+            Unit::Millimetre => "mm",
+            Unit::MillimetrePerHour => "mm/h",
+            Unit::MetrePerSecond => "m/s",
+            Unit::Degree => "°",
+            Unit::Lux => "lx",
+            Unit::Counts => "counts",
+            Unit::None => "",
+        }
+    }
+}
+
+/// A single value produced by a sensor.
+///
+/// Values are fixed-point: the physical value is `value / 10^precision`.
+#[derive(Format, Clone, Copy)]
+pub struct Measurement {
+    pub quantity: Quantity,
+    pub unit: Unit,
+    pub value: i32,
+    /// `value` before calibration
+    pub raw: i32,
+    pub precision: u8,
+    pub timestamp: Option<rtc::Timestamp>,
+}
+
+impl Measurement {
+    pub fn new(quantity: Quantity, unit: Unit, value: i32, precision: u8) -> Self {
+        Self {
+            quantity,
+            unit,
+            value,
+            raw: value,
+            precision,
+            timestamp: None,
+        }
+    }
+
+    /// 10^precision
+    fn scale(&self) -> f32 {
+        let mut scale = 1.0;
+        for _ in 0..self.precision {
+            scale *= 10.0;
+        }
+        scale
+    }
+
+    pub fn as_f32(&self) -> f32 {
+        self.value as f32 / self.scale()
+    }
+
+    pub fn raw_as_f32(&self) -> f32 {
+        self.raw as f32 / self.scale()
+    }
+
+    /// Sets the value, rounded to the measurement's precision
+    pub fn set_f32(&mut self, value: f32) {
+        self.value = libm::roundf(value * self.scale()) as i32;
+    }
+}
+
+/// A fixed-point value printed without its unit
+pub struct FixedPoint {
+    pub value: i32,
+    pub precision: u8,
+}
+
+impl fmt::Display for FixedPoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.precision == 0 {
+            return core::write!(f, "{}", self.value);
+        }
+
+        let scale = 10u32.pow(self.precision as u32);
+        let magnitude = self.value.unsigned_abs();
+        core::write!(
+            f,
+            "{}{}.{:0width$}",
+            if self.value < 0 { "-" } else { "" },
+            magnitude / scale,
+            magnitude % scale,
+            width = self.precision as usize
+        )
+    }
+}
+
+impl fmt::Display for Measurement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = FixedPoint {
+            value: self.value,
+            precision: self.precision,
+        };
+        core::write!(f, "{}", value)?;
+
+        match self.unit {
+            Unit::None => Ok(()),
+            unit => core::write!(f, " {}", unit.symbol()),
+        }
+    }
+}
+
+pub type Measurements = Vec<Measurement, MAX_MEASUREMENTS>;
+
+#[derive(Format, Clone, Copy, PartialEq, Eq)]
+pub enum SensorError {
+    /// The sensor didn't answer at all
+    NoResponse,
+    /// The sensor stopped answering midway
+    Timeout,
+    Checksum,
+    /// The reading was received intact but makes no physical sense
+    Implausible,
+    ReadFailed,
+}
+
+impl fmt::Display for SensorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SensorError::NoResponse => "no response",
+            SensorError::Timeout => "timeout",
+            SensorError::Checksum => "checksum error",
+            SensorError::Implausible => "implausible reading",
+            SensorError::ReadFailed => "read failed",
+        })
+    }
+}
+
+/// How often reading a sensor succeeded or failed, by kind of failure
+#[derive(Format, Clone, Copy, Default)]
+pub struct ErrorCounters {
+    pub successes: u32,
+    pub no_response: u32,
+    pub timeout: u32,
+    pub checksum: u32,
+    pub implausible: u32,
+    pub read_failed: u32,
+}
+
+impl ErrorCounters {
+    fn count(&mut self, result: Result<(), SensorError>) {
+        let counter = match result {
+            Ok(()) => &mut self.successes,
+            Err(SensorError::NoResponse) => &mut self.no_response,
+            Err(SensorError::Timeout) => &mut self.timeout,
+            Err(SensorError::Checksum) => &mut self.checksum,
+            Err(SensorError::Implausible) => &mut self.implausible,
+            Err(SensorError::ReadFailed) => &mut self.read_failed,
+        };
+        *counter = counter.saturating_add(1);
+    }
+}
+
+#[allow(async_fn_in_trait)]
+pub trait Sensor {
+    fn name(&self) -> &'static str;
+
+    async fn measure(&mut self) -> Result<Measurements, SensorError>;
+}
+
+/// All sensor types the station knows about, so that they can be kept
+/// together in the registry without dynamic dispatch.
+pub enum AnySensor {
+    Dht(DhtSensor),
+    Bme280(Bme280),
+    Sht(Sht),
+    Rain(RainGauge),
+    Wind(WindSensor),
+    Bh1750(Bh1750),
+    Veml6075(Veml6075),
+}
+
+impl Sensor for AnySensor {
+    fn name(&self) -> &'static str {
+        match self {
+            AnySensor::Dht(sensor) => sensor.name(),
+            AnySensor::Bme280(sensor) => sensor.name(),
+            AnySensor::Sht(sensor) => sensor.name(),
+            AnySensor::Rain(sensor) => sensor.name(),
+            AnySensor::Wind(sensor) => sensor.name(),
+            AnySensor::Bh1750(sensor) => sensor.name(),
+            AnySensor::Veml6075(sensor) => sensor.name(),
+        }
+    }
+
+    async fn measure(&mut self) -> Result<Measurements, SensorError> {
+        match self {
+            AnySensor::Dht(sensor) => sensor.measure().await,
+            AnySensor::Bme280(sensor) => sensor.measure().await,
+            AnySensor::Sht(sensor) => sensor.measure().await,
+            AnySensor::Rain(sensor) => sensor.measure().await,
+            AnySensor::Wind(sensor) => sensor.measure().await,
+            AnySensor::Bh1750(sensor) => sensor.measure().await,
+            AnySensor::Veml6075(sensor) => sensor.measure().await,
+        }
+    }
+}
+
+static SENSORS: Mutex<ThreadModeRawMutex, Vec<AnySensor, MAX_SENSORS>> = Mutex::new(Vec::new());
+
+pub async fn register(sensor: AnySensor) {
+    if SENSORS.lock().await.push(sensor).is_err() {
+        warn!("Sensor registry full");
+    }
+}
+
+/// The latest result of reading a sensor
+pub struct Reading {
+    pub sensor: &'static str,
+    pub result: Result<Measurements, SensorError>,
+    /// When the sensor was read
+    pub time: Instant,
+    pub counters: ErrorCounters,
+}
+
+impl Reading {
+    pub fn age(&self) -> Duration {
+        self.time.elapsed()
+    }
+}
+
+/// Latest readings in registration order. Only ever locked briefly, so
+/// synchronous handlers can read it while the sampler awaits a sensor.
+static LATEST: blocking_mutex::Mutex<ThreadModeRawMutex, RefCell<Vec<Reading, MAX_SENSORS>>> =
+    blocking_mutex::Mutex::new(RefCell::new(Vec::new()));
+
+/// Reads every registered sensor in registration order, timestamping the
+/// measurements with the current RTC time, and caches the results.
+pub async fn sample_all() {
+    let mut sensors = SENSORS.lock().await;
+    let timestamp = rtc::now().await.map(|dt| rtc::to_timestamp(&dt));
+
+    for (i, sensor) in sensors.iter_mut().enumerate() {
+        let result = sensor.measure().await.map(|mut measurements| {
+            calibration::apply(sensor.name(), &mut measurements);
+            for measurement in measurements.iter_mut() {
+                measurement.timestamp = timestamp;
+            }
+            measurements
+        });
+        let outcome = result.as_ref().map(|_| ()).map_err(|&e| e);
+        if let Err(e) = outcome {
+            warn!("Reading {} failed: {}", sensor.name(), e);
+        }
+
+        LATEST.lock(|latest| {
+            let mut latest = latest.borrow_mut();
+            match latest.get_mut(i) {
+                Some(reading) => {
+                    reading.counters.count(outcome);
+                    reading.result = result;
+                    reading.time = Instant::now();
+                }
+                None => {
+                    let mut counters = ErrorCounters::default();
+                    counters.count(outcome);
+                    // Cannot fail - at most as many readings as sensors
+                    let _ = latest.push(Reading {
+                        sensor: sensor.name(),
+                        result,
+                        time: Instant::now(),
+                        counters,
+                    });
+                }
+            }
+        });
+    }
+}
+
+/// Calls `f` with the latest reading of every sensor sampled so far
+pub fn latest<F: FnMut(&Reading)>(mut f: F) {
+    LATEST.lock(|latest| latest.borrow().iter().for_each(&mut f));
+}
+
+/// The latest measurement of `quantity` by the first sensor providing it
+pub fn latest_measurement(quantity: Quantity) -> Option<Measurement> {
+    let mut found: Option<Measurement> = None;
+    latest(|reading| {
+        if let Ok(measurements) = &reading.result {
+            if let Some(m) = measurements.iter().find(|m| m.quantity == quantity) {
+                found = found.or(Some(*m));
+            }
+        }
+    });
+    found
+}
@@ -0,0 +1,325 @@
+use defmt::{info, warn, Format};
+use embassy_time::{Duration, Instant, Timer};
+use embedded_hal_async::i2c::I2c as _;
+
+use super::{
+    i2c::Device,
+    sensor::{AnySensor, Measurement, Measurements, Quantity, Sensor, SensorError, Unit},
+};
+
+pub const DEFAULT_ADDRESS: u8 = 0x44;
+
+const SHT3X_SOFT_RESET: u16 = 0x30a2;
+const SHT3X_BREAK: u16 = 0x3093;
+const SHT3X_FETCH_DATA: u16 = 0xe000;
+const SHT3X_HEATER_ENABLE: u16 = 0x306d;
+const SHT3X_HEATER_DISABLE: u16 = 0x3066;
+
+const SHT4X_SOFT_RESET: u8 = 0x94;
+/// 200 mW for 1 s, followed by a high repeatability measurement
+const SHT4X_HEATER_200MW_1S: u8 = 0x39;
+
+/// Relative humidity (tenths of %) above which condensation on the sensor
+/// is assumed
+const CONDENSATION_THRESHOLD: i32 = 950;
+const HEATER_PULSE: Duration = Duration::from_secs(1);
+/// Minimum time between heater pulses
+const HEATER_INTERVAL: Duration = Duration::from_secs(10 * 60);
+/// Readings taken right after heating are skewed by the heater
+const HEATER_COOLDOWN: Duration = Duration::from_secs(30);
+
+#[derive(Format, Clone, Copy, PartialEq, Eq)]
+pub enum Model {
+    Sht3x,
+    Sht4x,
+}
+
+#[derive(Format, Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code)]
+pub enum Repeatability {
+    High,
+    Medium,
+    Low,
+}
+
+/// Periodic acquisition rate, SHT3x only
+#[derive(Format, Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code)]
+pub enum Rate {
+    HalfPerSecond,
+    OnePerSecond,
+    TwoPerSecond,
+    FourPerSecond,
+    TenPerSecond,
+}
+
+#[derive(Format, Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code)]
+pub enum Mode {
+    SingleShot,
+    Periodic(Rate),
+}
+
+#[derive(Format, Clone, Copy, PartialEq, Eq)]
+pub enum ShtError {
+    I2c,
+    Crc,
+    /// Periodic mode has no new measurement yet
+    NoData,
+    /// The heater ran recently, readings are skewed
+    Cooldown,
+    Unsupported,
+}
+
+/// CRC-8 with polynomial 0x31 and initial value 0xFF, as used by Sensirion
+fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0xffu8;
+    for byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x31
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Splits a response into its CRC-verified 16-bit words
+fn words<const N: usize>(response: &[u8]) -> Result<[u16; N], ShtError> {
+    let mut words = [0; N];
+    for (word, chunk) in words.iter_mut().zip(response.chunks_exact(3)) {
+        if crc8(&chunk[..2]) != chunk[2] {
+            return Err(ShtError::Crc);
+        }
+        *word = u16::from_be_bytes([chunk[0], chunk[1]]);
+    }
+    Ok(words)
+}
+
+impl Model {
+    fn single_shot(&self, repeatability: Repeatability) -> (&'static [u8], Duration) {
+        match (self, repeatability) {
+            // Clock stretching disabled
+            (Model::Sht3x, Repeatability::High) => (&[0x24, 0x00], Duration::from_micros(15500)),
+            (Model::Sht3x, Repeatability::Medium) => (&[0x24, 0x0b], Duration::from_micros(6500)),
+            (Model::Sht3x, Repeatability::Low) => (&[0x24, 0x16], Duration::from_micros(4500)),
+            (Model::Sht4x, Repeatability::High) => (&[0xfd], Duration::from_micros(8300)),
+            (Model::Sht4x, Repeatability::Medium) => (&[0xf6], Duration::from_micros(4500)),
+            (Model::Sht4x, Repeatability::Low) => (&[0xe0], Duration::from_micros(1600)),
+        }
+    }
+
+    fn periodic(&self, rate: Rate, repeatability: Repeatability) -> Result<u16, ShtError> {
+        if *self != Model::Sht3x {
+            return Err(ShtError::Unsupported);
+        }
+
+        let (msb, lsbs) = match rate {
+            Rate::HalfPerSecond => (0x20, [0x32, 0x24, 0x2f]),
+            Rate::OnePerSecond => (0x21, [0x30, 0x26, 0x2d]),
+            Rate::TwoPerSecond => (0x22, [0x36, 0x20, 0x2b]),
+            Rate::FourPerSecond => (0x23, [0x34, 0x22, 0x29]),
+            Rate::TenPerSecond => (0x27, [0x37, 0x21, 0x2a]),
+        };
+        let lsb = match repeatability {
+            Repeatability::High => lsbs[0],
+            Repeatability::Medium => lsbs[1],
+            Repeatability::Low => lsbs[2],
+        };
+        Ok(u16::from_be_bytes([msb, lsb]))
+    }
+
+    /// Converts raw words to temperature (0.01 °C) and humidity (0.1 %)
+    fn convert(&self, raw_temperature: u16, raw_humidity: u16) -> (i32, i32) {
+        let temperature = (17500 * raw_temperature as i32 + 32767) / 65535 - 4500;
+        let humidity = match self {
+            Model::Sht3x => (1000 * raw_humidity as i32 + 32767) / 65535,
+            Model::Sht4x => (1250 * raw_humidity as i32 + 32767) / 65535 - 60,
+        };
+        (temperature, humidity.clamp(0, 1000))
+    }
+}
+
+pub struct Sht {
+    i2c: Device,
+    address: u8,
+    model: Model,
+    mode: Mode,
+    repeatability: Repeatability,
+    last_heated: Option<Instant>,
+}
+
+impl Sht {
+    async fn command(&mut self, command: u16) -> Result<(), ShtError> {
+        self.i2c
+            .write(self.address, &command.to_be_bytes())
+            .await
+            .map_err(|_| ShtError::I2c)
+    }
+
+    async fn read_words<const N: usize>(&mut self) -> Result<[u16; N], ShtError> {
+        let mut response = [0; 6];
+        let response = &mut response[..N * 3];
+        self.i2c
+            .read(self.address, response)
+            .await
+            .map_err(|_| ShtError::I2c)?;
+        words(response)
+    }
+
+    pub async fn new(
+        i2c: Device,
+        address: u8,
+        model: Model,
+        mode: Mode,
+        repeatability: Repeatability,
+    ) -> Result<Self, ShtError> {
+        let mut sensor = Self {
+            i2c,
+            address,
+            model,
+            mode,
+            repeatability,
+            last_heated: None,
+        };
+
+        match model {
+            Model::Sht3x => {
+                // Leave a periodic mode possibly started before a soft reboot
+                sensor.command(SHT3X_BREAK).await?;
+                Timer::after_millis(1).await;
+                sensor.command(SHT3X_SOFT_RESET).await?;
+            }
+            Model::Sht4x => {
+                sensor
+                    .i2c
+                    .write(address, &[SHT4X_SOFT_RESET])
+                    .await
+                    .map_err(|_| ShtError::I2c)?;
+            }
+        }
+        Timer::after_millis(2).await;
+
+        if let Mode::Periodic(rate) = mode {
+            let command = model.periodic(rate, repeatability)?;
+            sensor.command(command).await?;
+        }
+
+        Ok(sensor)
+    }
+
+    pub async fn read(&mut self) -> Result<(i32, i32), ShtError> {
+        if self
+            .last_heated
+            .is_some_and(|t| t.elapsed() < HEATER_COOLDOWN)
+        {
+            return Err(ShtError::Cooldown);
+        }
+
+        let [raw_temperature, raw_humidity] = match self.mode {
+            Mode::SingleShot => {
+                let (command, duration) = self.model.single_shot(self.repeatability);
+                self.i2c
+                    .write(self.address, command)
+                    .await
+                    .map_err(|_| ShtError::I2c)?;
+                Timer::after(duration).await;
+                self.read_words::<2>().await?
+            }
+            // The sensor NACKs the read when no new measurement is available
+            Mode::Periodic(_) => {
+                self.command(SHT3X_FETCH_DATA).await?;
+                self.read_words::<2>().await.map_err(|e| match e {
+                    ShtError::I2c => ShtError::NoData,
+                    e => e,
+                })?
+            }
+        };
+
+        Ok(self.model.convert(raw_temperature, raw_humidity))
+    }
+
+    /// Runs the on-chip heater for about a second to evaporate condensation
+    pub async fn heat(&mut self) -> Result<(), ShtError> {
+        info!("Heating {} to recover from condensation", self.model);
+
+        match self.model {
+            Model::Sht3x => {
+                self.command(SHT3X_HEATER_ENABLE).await?;
+                Timer::after(HEATER_PULSE).await;
+                self.command(SHT3X_HEATER_DISABLE).await?;
+            }
+            Model::Sht4x => {
+                self.i2c
+                    .write(self.address, &[SHT4X_HEATER_200MW_1S])
+                    .await
+                    .map_err(|_| ShtError::I2c)?;
+                // Heating ends with a measurement, which is discarded
+                Timer::after(HEATER_PULSE + Duration::from_millis(10)).await;
+                self.read_words::<2>().await?;
+            }
+        }
+
+        self.last_heated = Some(Instant::now());
+        Ok(())
+    }
+}
+
+impl Sensor for Sht {
+    fn name(&self) -> &'static str {
+        match self.model {
+            Model::Sht3x => "sht3x",
+            Model::Sht4x => "sht4x",
+        }
+    }
+
+    async fn measure(&mut self) -> Result<Measurements, SensorError> {
+        let (temperature, humidity) = self.read().await.map_err(|e| {
+            warn!("{} read failed: {}", self.model, e);
+            match e {
+                ShtError::Crc => SensorError::Checksum,
+                _ => SensorError::ReadFailed,
+            }
+        })?;
+
+        let heater_due = match self.last_heated {
+            Some(t) => t.elapsed() >= HEATER_INTERVAL,
+            None => true,
+        };
+        if humidity >= CONDENSATION_THRESHOLD && heater_due {
+            if let Err(e) = self.heat().await {
+                warn!("{} heater failed: {}", self.model, e);
+            }
+        }
+
+        let mut measurements = Measurements::new();
+        // Cannot fail - fewer measurements than MAX_MEASUREMENTS
+        let _ = measurements.push(Measurement::new(
+            Quantity::Temperature,
+            Unit::Celsius,
+            temperature,
+            2,
+        ));
+        let _ = measurements.push(Measurement::new(
+            Quantity::RelativeHumidity,
+            Unit::Percent,
+            humidity,
+            1,
+        ));
+
+        Ok(measurements)
+    }
+}
+
+pub async fn init(i2c: Device, model: Model, mode: Mode) {
+    match Sht::new(i2c, DEFAULT_ADDRESS, model, mode, Repeatability::High).await {
+        Ok(sensor) => {
+            info!("Found {} at {:#x}", model, DEFAULT_ADDRESS);
+            super::sensor::register(AnySensor::Sht(sensor)).await;
+        }
+        Err(e) => warn!("No {} found: {}", model, e),
+    }
+}
@@ -0,0 +1,89 @@
+use core::cell::RefCell;
+
+use embassy_rp::{
+    adc::Channel,
+    gpio::Pull,
+    pac,
+    peripherals::{ADC_TEMP_SENSOR, PIN_29},
+};
+use embassy_sync::{
+    blocking_mutex::{raw::ThreadModeRawMutex, Mutex},
+    signal::Signal,
+};
+use embassy_time::Timer;
+
+use super::adc;
+
+/// cyw43 SPI chip select and clock pins, the clock doubling as VSYS/3 input
+const WL_CS: usize = 25;
+const WL_CLK_VSYS: usize = 29;
+const FUNCSEL_NULL: u8 = 0x1f;
+
+const ADC_VREF: f32 = 3.3;
+const ADC_STEPS: f32 = 4096.0;
+
+static REBOOT: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
+static TEMPERATURE: Mutex<ThreadModeRawMutex, RefCell<Option<Channel<'static>>>> =
+    Mutex::new(RefCell::new(None));
+
+pub fn init(temperature_sensor: ADC_TEMP_SENSOR) {
+    let channel = Channel::new_temp_sensor(temperature_sensor);
+    TEMPERATURE.lock(|cell| cell.replace(Some(channel)));
+}
+
+fn to_volts(reading: u16) -> f32 {
+    reading as f32 * ADC_VREF / ADC_STEPS
+}
+
+/// RP2040 die temperature in °C, from datasheet section 4.9.5
+pub fn chip_temperature() -> Option<f32> {
+    let reading = TEMPERATURE.lock(|cell| adc::read(cell.borrow_mut().as_mut()?))?;
+
+    Some(27.0 - (to_volts(reading) - 0.706) / 0.001721)
+}
+
+/// VSYS in volts, `None` if the WiFi chip is being talked to
+///
+/// GPIO29 is the cyw43 SPI clock as well as the VSYS/3 divider input. The
+/// cyw43 driver keeps chip select (GPIO25) low for a whole transaction, so
+/// the pin is only borrowed while it is high. Nothing awaits in between,
+/// which keeps the cyw43 runner task from starting a transaction meanwhile.
+pub fn supply_voltage() -> Option<f32> {
+    critical_section::with(|_| {
+        if pac::SIO.gpio_in(0).read() & (1 << WL_CS) == 0 {
+            return None;
+        }
+
+        let ctrl = pac::IO_BANK0.gpio(WL_CLK_VSYS).ctrl();
+        let pad = pac::PADS_BANK0.gpio(WL_CLK_VSYS);
+        let saved_ctrl = ctrl.read();
+        let saved_pad = pad.read();
+
+        // Disconnect the PIO from the pin so it doesn't drive the clock level
+        ctrl.modify(|w| w.set_funcsel(FUNCSEL_NULL));
+        // SAFETY: the pin is owned by the cyw43 SPI, which is idle and is
+        // given the pin back below
+        let mut channel = Channel::new_pin(unsafe { PIN_29::steal() }, Pull::None);
+        let reading = adc::read(&mut channel);
+        drop(channel);
+
+        pad.write_value(saved_pad);
+        ctrl.write_value(saved_ctrl);
+
+        Some(to_volts(reading?) * 3.0)
+    })
+}
+
+/// Asks for a reboot, made after a second to let the request asking for it
+/// be answered
+pub fn schedule_reboot() {
+    REBOOT.signal(());
+}
+
+/// Reboots once asked to
+pub async fn reboot_when_scheduled() -> ! {
+    REBOOT.wait().await;
+    Timer::after_secs(1).await;
+    cortex_m::peripheral::SCB::sys_reset()
+}
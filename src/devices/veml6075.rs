@@ -0,0 +1,172 @@
+use defmt::{info, warn, Format};
+use embassy_time::{Duration, Timer};
+use embedded_hal_async::i2c::I2c as _;
+
+use super::{
+    i2c::Device,
+    sensor::{AnySensor, Measurement, Measurements, Quantity, Sensor, SensorError, Unit},
+};
+
+pub const ADDRESS: u8 = 0x10;
+
+const REG_UV_CONF: u8 = 0x00;
+const REG_UVA: u8 = 0x07;
+const REG_UVB: u8 = 0x09;
+const REG_UVCOMP1: u8 = 0x0a;
+const REG_UVCOMP2: u8 = 0x0b;
+const REG_ID: u8 = 0x0c;
+
+const DEVICE_ID: u16 = 0x0026;
+
+const UV_CONF_TRIGGER: u8 = 1 << 2;
+const UV_CONF_ACTIVE_FORCE: u8 = 1 << 1;
+
+/// Visible and IR compensation coefficients for an open sensor without a
+/// diffuser, from Vishay's "Designing the VEML6075 into an application"
+const UVA_A: f32 = 2.22;
+const UVA_B: f32 = 1.33;
+const UVB_C: f32 = 2.95;
+const UVB_D: f32 = 1.74;
+/// UV index per compensated count at 100 ms integration time
+const UVA_RESPONSIVITY: f32 = 0.001461;
+const UVB_RESPONSIVITY: f32 = 0.002591;
+
+#[derive(Format, Clone, Copy, PartialEq, Eq)]
+pub enum Veml6075Error {
+    I2c,
+    UnknownDevice(u16),
+}
+
+#[derive(Format, Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code)]
+pub enum IntegrationTime {
+    Ms50 = 0,
+    Ms100 = 1,
+    Ms200 = 2,
+    Ms400 = 3,
+    Ms800 = 4,
+}
+
+impl IntegrationTime {
+    fn millis(&self) -> u64 {
+        50 << (*self as u64)
+    }
+}
+
+/// Compensated readings, normalised to 100 ms integration time
+pub struct UvReading {
+    pub uva: f32,
+    pub uvb: f32,
+    pub index: f32,
+}
+
+pub struct Veml6075 {
+    i2c: Device,
+    integration_time: IntegrationTime,
+}
+
+impl Veml6075 {
+    async fn read_register(&mut self, register: u8) -> Result<u16, Veml6075Error> {
+        let mut value = [0; 2];
+        self.i2c
+            .write_read(ADDRESS, &[register], &mut value)
+            .await
+            .map_err(|_| Veml6075Error::I2c)?;
+        Ok(u16::from_le_bytes(value))
+    }
+
+    async fn write_config(&mut self, flags: u8) -> Result<(), Veml6075Error> {
+        let config = (self.integration_time as u8) << 4 | UV_CONF_ACTIVE_FORCE | flags;
+        self.i2c
+            .write(ADDRESS, &[REG_UV_CONF, config, 0])
+            .await
+            .map_err(|_| Veml6075Error::I2c)
+    }
+
+    pub async fn new(
+        i2c: Device,
+        integration_time: IntegrationTime,
+    ) -> Result<Self, Veml6075Error> {
+        let mut sensor = Self {
+            i2c,
+            integration_time,
+        };
+
+        let id = sensor.read_register(REG_ID).await?;
+        if id & 0xff != DEVICE_ID {
+            return Err(Veml6075Error::UnknownDevice(id));
+        }
+
+        // Active force mode: power up, but only measure when triggered
+        sensor.write_config(0).await?;
+
+        Ok(sensor)
+    }
+
+    pub async fn read(&mut self) -> Result<UvReading, Veml6075Error> {
+        self.write_config(UV_CONF_TRIGGER).await?;
+        // Both channels are converted one after the other
+        Timer::after(Duration::from_millis(
+            2 * self.integration_time.millis() + 10,
+        ))
+        .await;
+
+        let uva = self.read_register(REG_UVA).await? as f32;
+        let uvb = self.read_register(REG_UVB).await? as f32;
+        let comp1 = self.read_register(REG_UVCOMP1).await? as f32;
+        let comp2 = self.read_register(REG_UVCOMP2).await? as f32;
+
+        let scale = 100.0 / self.integration_time.millis() as f32;
+        let uva = ((uva - UVA_A * comp1 - UVA_B * comp2) * scale).max(0.0);
+        let uvb = ((uvb - UVB_C * comp1 - UVB_D * comp2) * scale).max(0.0);
+        let index = (uva * UVA_RESPONSIVITY + uvb * UVB_RESPONSIVITY) / 2.0;
+
+        Ok(UvReading { uva, uvb, index })
+    }
+}
+
+impl Sensor for Veml6075 {
+    fn name(&self) -> &'static str {
+        "veml6075"
+    }
+
+    async fn measure(&mut self) -> Result<Measurements, SensorError> {
+        let reading = self.read().await.map_err(|e| {
+            warn!("VEML6075 read failed: {}", e);
+            SensorError::ReadFailed
+        })?;
+
+        let mut measurements = Measurements::new();
+        // Cannot fail - fewer measurements than MAX_MEASUREMENTS
+        let _ = measurements.push(Measurement::new(
+            Quantity::UvIndex,
+            Unit::None,
+            (reading.index * 10.0 + 0.5) as i32,
+            1,
+        ));
+        let _ = measurements.push(Measurement::new(
+            Quantity::UvA,
+            Unit::Counts,
+            (reading.uva + 0.5) as i32,
+            0,
+        ));
+        let _ = measurements.push(Measurement::new(
+            Quantity::UvB,
+            Unit::Counts,
+            (reading.uvb + 0.5) as i32,
+            0,
+        ));
+
+        Ok(measurements)
+    }
+}
+
+pub async fn init(i2c: Device) {
+    match Veml6075::new(i2c, IntegrationTime::Ms100).await {
+        Ok(sensor) => {
+            info!("Found VEML6075 at {:#x}", ADDRESS);
+            super::sensor::register(AnySensor::Veml6075(sensor)).await;
+        }
+        Err(e) => warn!("No VEML6075 found: {}", e),
+    }
+}
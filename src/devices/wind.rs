@@ -0,0 +1,154 @@
+mod averaging;
+
+pub use averaging::compass_point;
+use averaging::Sample;
+use embassy_rp::{
+    adc::Channel,
+    gpio::{AnyPin, Input, Pull},
+};
+use embassy_sync::{blocking_mutex::raw::ThreadModeRawMutex, mutex::Mutex};
+use embassy_time::{Duration, Ticker, Timer};
+use heapless::Deque;
+use portable_atomic::{AtomicU32, Ordering};
+
+use super::{
+    adc,
+    sensor::{AnySensor, Measurement, Measurements, Quantity, Sensor, SensorError, Unit},
+};
+use crate::config;
+
+/// Reed switch contact bounce, well below the pulse period in a storm
+const DEBOUNCE: Duration = Duration::from_millis(2);
+/// Two minutes for the mean wind, ten for the gust, at one sample a second
+const MEAN_SAMPLES: usize = 120;
+const GUST_SAMPLES: usize = 600;
+const GUST_WINDOW: usize = 3;
+
+/// Vane resistance per compass point (N first, clockwise) for the common
+/// resistor ladder vanes, read through a 10 kΩ pull-up to 3.3 V
+const VANE_RESISTANCES: [u32; 16] = [
+    33000, 6570, 8200, 891, 1000, 688, 2200, 1410, 3900, 3140, 16000, 14120, 120000, 42120, 64900,
+    21880,
+];
+const VANE_PULL_UP: u32 = 10000;
+const ADC_MAX: u32 = 4095;
+
+static PULSES: AtomicU32 = AtomicU32::new(0);
+static SAMPLES: Mutex<ThreadModeRawMutex, Deque<Sample, GUST_SAMPLES>> = Mutex::new(Deque::new());
+
+/// Wind speeds in mm/s, direction in degrees (`None` when calm)
+#[derive(Clone, Copy)]
+pub struct Wind {
+    pub speed: u32,
+    pub gust: u32,
+    pub direction: Option<u16>,
+}
+
+/// Maps a vane reading to the compass point with the closest expected value
+fn vane_direction(reading: u16) -> u8 {
+    let expected = |r: u32| ADC_MAX * r / (r + VANE_PULL_UP);
+
+    VANE_RESISTANCES
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, r)| expected(**r).abs_diff(reading as u32))
+        .map(|(i, _)| i as u8)
+        .unwrap_or(0)
+}
+
+/// Counts cup anemometer pulses pulling `pin` low
+pub async fn count_pulses(pin: AnyPin) -> ! {
+    let mut input = Input::new(pin, Pull::Up);
+
+    loop {
+        input.wait_for_falling_edge().await;
+        PULSES.fetch_add(1, Ordering::Relaxed);
+        Timer::after(DEBOUNCE).await;
+    }
+}
+
+/// Samples speed and direction every second
+pub async fn sample(mut vane: Channel<'static>) -> ! {
+    let mut ticker = Ticker::every(Duration::from_secs(1));
+    PULSES.store(0, Ordering::Relaxed);
+
+    loop {
+        ticker.next().await;
+
+        let pulses = PULSES.swap(0, Ordering::Relaxed);
+        let per_hz = config::get().await.wind_per_hz as u32;
+        let speed = (pulses * per_hz).min(u16::MAX as u32) as u16;
+
+        let reading = adc::read(&mut vane);
+
+        let mut samples = SAMPLES.lock().await;
+        // Keep the last direction if the ADC fails, rather than reporting north
+        let direction = match reading {
+            Some(reading) => vane_direction(reading),
+            None => samples.back().map(|s| s.direction).unwrap_or(0),
+        };
+
+        if samples.is_full() {
+            samples.pop_front();
+        }
+        // Cannot fail - room was made above
+        let _ = samples.push_back(Sample { speed, direction });
+    }
+}
+
+pub async fn wind() -> Option<Wind> {
+    let samples = SAMPLES.lock().await;
+    if samples.len() < MEAN_SAMPLES {
+        return None;
+    }
+
+    let recent = || samples.iter().skip(samples.len() - MEAN_SAMPLES).copied();
+    Some(Wind {
+        speed: averaging::mean_speed(recent()),
+        gust: averaging::gust::<GUST_WINDOW>(samples.iter().copied()),
+        direction: averaging::vector_direction(recent()),
+    })
+}
+
+pub async fn init() {
+    super::sensor::register(AnySensor::Wind(WindSensor)).await;
+}
+
+/// Registry entry reporting the averaged wind
+pub struct WindSensor;
+
+impl Sensor for WindSensor {
+    fn name(&self) -> &'static str {
+        "wind"
+    }
+
+    async fn measure(&mut self) -> Result<Measurements, SensorError> {
+        let wind = wind().await.ok_or(SensorError::ReadFailed)?;
+
+        let mut measurements = Measurements::new();
+        // Cannot fail - fewer measurements than MAX_MEASUREMENTS
+        // mm/s rounded to m/s with one decimal
+        let _ = measurements.push(Measurement::new(
+            Quantity::WindSpeed,
+            Unit::MetrePerSecond,
+            ((wind.speed + 50) / 100) as i32,
+            1,
+        ));
+        let _ = measurements.push(Measurement::new(
+            Quantity::WindGust,
+            Unit::MetrePerSecond,
+            ((wind.gust + 50) / 100) as i32,
+            1,
+        ));
+        if let Some(direction) = wind.direction {
+            let _ = measurements.push(Measurement::new(
+                Quantity::WindDirection,
+                Unit::Degree,
+                direction as i32,
+                0,
+            ));
+        }
+
+        Ok(measurements)
+    }
+}
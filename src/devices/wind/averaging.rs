@@ -0,0 +1,155 @@
+//! WMO-style wind averaging over one-second samples
+
+pub const COMPASS_POINTS: [&str; 16] = [
+    "N", "NNE", "NE", "ENE", "E", "ESE", "SE", "SSE", "S", "SSW", "SW", "WSW", "W", "WNW", "NW",
+    "NNW",
+];
+
+/// sin and cos of the 16 compass points, clockwise from north
+const UNIT_VECTORS: [(f32, f32); 16] = [
+    (0.0, 1.0),
+    (0.382_683_43, 0.923_879_5),
+    (0.707_106_77, 0.707_106_77),
+    (0.923_879_5, 0.382_683_43),
+    (1.0, 0.0),
+    (0.923_879_5, -0.382_683_43),
+    (0.707_106_77, -0.707_106_77),
+    (0.382_683_43, -0.923_879_5),
+    (0.0, -1.0),
+    (-0.382_683_43, -0.923_879_5),
+    (-0.707_106_77, -0.707_106_77),
+    (-0.923_879_5, -0.382_683_43),
+    (-1.0, 0.0),
+    (-0.923_879_5, 0.382_683_43),
+    (-0.707_106_77, 0.707_106_77),
+    (-0.382_683_43, 0.923_879_5),
+];
+
+#[derive(Clone, Copy, Default)]
+pub struct Sample {
+    /// mm/s
+    pub speed: u16,
+    /// Compass point index, 0 = N, clockwise
+    pub direction: u8,
+}
+
+/// Mean speed in mm/s, 0 without samples
+pub fn mean_speed(samples: impl Iterator<Item = Sample>) -> u32 {
+    let (sum, count) = samples.fold((0u32, 0u32), |(sum, count), s| {
+        (sum + s.speed as u32, count + 1)
+    });
+    sum.checked_div(count).unwrap_or(0)
+}
+
+/// Highest mean speed over `WINDOW` consecutive samples (3 s for WMO gusts)
+pub fn gust<const WINDOW: usize>(samples: impl Iterator<Item = Sample>) -> u32 {
+    let mut window = [0u32; WINDOW];
+    let mut sum = 0;
+    let mut gust = 0;
+
+    for (i, sample) in samples.enumerate() {
+        let slot = &mut window[i % WINDOW];
+        sum = sum - *slot + sample.speed as u32;
+        *slot = sample.speed as u32;
+        if i + 1 >= WINDOW {
+            gust = gust.max(sum / WINDOW as u32);
+        }
+    }
+
+    gust
+}
+
+/// Speed-weighted vector mean direction in degrees, `None` when calm
+pub fn vector_direction(samples: impl Iterator<Item = Sample>) -> Option<u16> {
+    let (east, north) = samples.fold((0.0f32, 0.0f32), |(east, north), s| {
+        let (sin, cos) = UNIT_VECTORS[s.direction as usize % 16];
+        (east + s.speed as f32 * sin, north + s.speed as f32 * cos)
+    });
+
+    if east == 0.0 && north == 0.0 {
+        return None;
+    }
+
+    let degrees = libm::atan2f(east, north).to_degrees();
+    let degrees = if degrees < 0.0 {
+        degrees + 360.0
+    } else {
+        degrees
+    };
+    Some((degrees + 0.5) as u16 % 360)
+}
+
+/// Nearest of the 16 compass points for a direction in degrees
+pub fn compass_point(degrees: u16) -> &'static str {
+    // Each point covers 22.5°, centred on it
+    COMPASS_POINTS[((degrees as u32 * 10 + 112) / 225 % 16) as usize]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples(samples: &[(u16, u8)]) -> impl Iterator<Item = Sample> + '_ {
+        samples
+            .iter()
+            .map(|&(speed, direction)| Sample { speed, direction })
+    }
+
+    #[test]
+    fn mean_speed_of_samples() {
+        assert_eq!(mean_speed(samples(&[])), 0);
+        assert_eq!(
+            mean_speed(samples(&[(1000, 0), (2000, 4), (3000, 8)])),
+            2000
+        );
+    }
+
+    #[test]
+    fn gust_is_highest_window_mean() {
+        let speeds = [(0, 0), (3000, 0), (3000, 0), (3000, 0), (0, 0), (6000, 0)];
+        assert_eq!(gust::<3>(samples(&speeds)), 3000);
+        // Not a single full window yet
+        assert_eq!(gust::<3>(samples(&speeds[..2])), 0);
+    }
+
+    #[test]
+    fn direction_averages_across_north() {
+        // NNW and NNE, i.e. 337.5° and 22.5°, are north, not south
+        assert_eq!(vector_direction(samples(&[(1000, 15), (1000, 1)])), Some(0));
+        assert_eq!(vector_direction(samples(&[(1000, 0), (1000, 4)])), Some(45));
+        assert_eq!(vector_direction(samples(&[(1000, 15)])), Some(338));
+    }
+
+    #[test]
+    fn direction_is_weighted_by_speed() {
+        assert_eq!(vector_direction(samples(&[(3000, 0), (1000, 8)])), Some(0));
+        assert_eq!(
+            vector_direction(samples(&[(1000, 0), (3000, 8)])),
+            Some(180)
+        );
+    }
+
+    #[test]
+    fn calm_has_no_direction() {
+        assert_eq!(vector_direction(samples(&[])), None);
+        assert_eq!(vector_direction(samples(&[(0, 4), (0, 12)])), None);
+        assert_eq!(vector_direction(samples(&[(1000, 0), (1000, 8)])), None);
+    }
+
+    #[test]
+    fn compass_points() {
+        for (degrees, point) in [
+            (0, "N"),
+            (10, "N"),
+            (350, "N"),
+            (359, "N"),
+            (12, "NNE"),
+            (45, "NE"),
+            (180, "S"),
+            (200, "SSW"),
+            (337, "NNW"),
+        ] {
+            assert_eq!(compass_point(degrees), point, "{}°", degrees);
+        }
+    }
+}
@@ -1,14 +1,140 @@
+mod api;
+mod export;
+mod settings;
+
+pub use api::{write_history_status, write_network_status, write_wifi_scan};
+pub use settings::{
+    reboot, set_config, set_config_json, set_setup, write_config, write_config_json,
+    write_config_login, write_setup,
+};
+
 use crate::{
-    devices,
-    http::{GetAs, GetStr, KeyValueMap, StatusCode},
+    calibration::{self, Calibration, CalibrationError},
+    config,
+    devices::{
+        self,
+        sensor::{FixedPoint, Quantity, Unit},
+    },
+    history,
+    http::{BodyStream, ContentType, GetAs, GetStr, KeyValueMap, StatusCode},
+    meteo, network, pressure,
+    statistics::{self, Summary},
+};
+use core::{
+    fmt::{self, Display, Write},
+    ops::RangeInclusive,
 };
-use core::fmt::Write;
 use embassy_futures::block_on;
 use embassy_rp::rtc::{DateTime, DayOfWeek};
-use heapless::Vec;
+use embassy_time::Instant;
+use heapless::{String, Vec};
 
 pub const INDEX: &str = include_str!("../static/index.html");
 
+/// Lowest and highest inhabited places, give or take
+const ALTITUDES: RangeInclusive<i16> = -500..=9000;
+/// The DHT22 needs 2 s between reads
+const SAMPLE_INTERVALS: RangeInclusive<u16> = 2..=3600;
+const LOG_INTERVALS: RangeInclusive<u16> = 10..=3600;
+const MM_PER_TIP: RangeInclusive<f32> = 0.01..=10.0;
+const MS_PER_HZ: RangeInclusive<f32> = 0.01..=10.0;
+/// Room left in a chunk before writing the statistics of one more series
+const MAX_SERIES_HTML: usize = 512;
+
+/// Escapes text for HTML element content and quoted attributes
+struct Html<T>(T);
+
+impl<T: Display> Display for Html<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        /// Escapes whatever is written through it
+        struct Escaping<'a, 'b>(&'a mut fmt::Formatter<'b>);
+
+        impl Write for Escaping<'_, '_> {
+            fn write_str(&mut self, text: &str) -> fmt::Result {
+                for c in text.chars() {
+                    match c {
+                        '&' => self.0.write_str("&amp;")?,
+                        '<' => self.0.write_str("&lt;")?,
+                        '>' => self.0.write_str("&gt;")?,
+                        '"' => self.0.write_str("&quot;")?,
+                        '\'' => self.0.write_str("&#39;")?,
+                        c => self.0.write_char(c)?,
+                    }
+                }
+                Ok(())
+            }
+        }
+
+        core::write!(Escaping(f), "{}", self.0)
+    }
+}
+
+/// Escapes text for a JSON string
+struct Json<'a>(&'a str);
+
+impl Display for Json<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for c in self.0.chars() {
+            match c {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                c if (c as u32) < 0x20 => core::write!(f, "\\u{:04x}", c as u32)?,
+                c => f.write_char(c)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Responses too large to buffer, streamed by the server
+pub enum Stream {
+    History(api::HistoryStream),
+    Export(export::ExportStream),
+    Statistics(StatisticsStream),
+}
+
+impl BodyStream for Stream {
+    fn content_type(&self) -> ContentType {
+        match self {
+            Stream::History(stream) => stream.content_type(),
+            Stream::Export(stream) => stream.content_type(),
+            Stream::Statistics(stream) => stream.content_type(),
+        }
+    }
+
+    fn attachment(&self) -> Option<&'static str> {
+        match self {
+            Stream::History(stream) => stream.attachment(),
+            Stream::Export(stream) => stream.attachment(),
+            Stream::Statistics(stream) => stream.attachment(),
+        }
+    }
+
+    fn fill<const N: usize>(&mut self, chunk: &mut Vec<u8, N>) -> bool {
+        match self {
+            Stream::History(stream) => stream.fill(chunk),
+            Stream::Export(stream) => stream.fill(chunk),
+            Stream::Statistics(stream) => stream.fill(chunk),
+        }
+    }
+}
+
+pub fn history(parameters: Option<&KeyValueMap>) -> Result<Stream, StatusCode> {
+    api::HistoryStream::new(parameters).map(Stream::History)
+}
+
+pub fn export_csv(parameters: Option<&KeyValueMap>) -> Result<Stream, StatusCode> {
+    export::ExportStream::new(export::Format::Csv, parameters).map(Stream::Export)
+}
+
+pub fn export_ndjson(parameters: Option<&KeyValueMap>) -> Result<Stream, StatusCode> {
+    export::ExportStream::new(export::Format::Ndjson, parameters).map(Stream::Export)
+}
+
+pub fn statistics(parameters: Option<&KeyValueMap>) -> Result<Stream, StatusCode> {
+    StatisticsStream::new(parameters).map(Stream::Statistics)
+}
+
 pub fn write_time<const BUF_SIZE: usize>(buffer: &mut Vec<u8, BUF_SIZE>) {
     let now = block_on(devices::rtc::now());
     if let Some(dt) = now {
@@ -29,11 +155,18 @@ pub fn write_time<const BUF_SIZE: usize>(buffer: &mut Vec<u8, BUF_SIZE>) {
 pub fn set_time(content: Option<&KeyValueMap>) -> Result<(), StatusCode> {
     let content = content.ok_or(StatusCode::BadRequest)?;
 
-    let year = content.get_as("y").map_err(|_| StatusCode::BadRequest)?;
-    let month = content.get_as("mo").map_err(|_| StatusCode::BadRequest)?;
-    let day = content.get_as("d").map_err(|_| StatusCode::BadRequest)?;
-    let hour = content.get_as("h").map_err(|_| StatusCode::BadRequest)?;
-    let minute = content.get_as("m").map_err(|_| StatusCode::BadRequest)?;
+    let year: u16 = content.get_as("y").map_err(|_| StatusCode::BadRequest)?;
+    let month: u8 = content.get_as("mo").map_err(|_| StatusCode::BadRequest)?;
+    let day: u8 = content.get_as("d").map_err(|_| StatusCode::BadRequest)?;
+    let hour: u8 = content.get_as("h").map_err(|_| StatusCode::BadRequest)?;
+    let minute: u8 = content.get_as("m").map_err(|_| StatusCode::BadRequest)?;
+    // Readings are timestamped from 1970 on
+    if !devices::rtc::is_valid_date(year as u32, month as u32, day as u32)
+        || hour > 23
+        || minute > 59
+    {
+        return Err(StatusCode::UnprocessableContent);
+    }
     let day_of_week = match content
         .get_str("day_of_week")
         .map_err(|_| StatusCode::BadRequest)?
@@ -64,14 +197,491 @@ pub fn set_time(content: Option<&KeyValueMap>) -> Result<(), StatusCode> {
 }
 
 pub fn write_temperature<const BUF_SIZE: usize>(buffer: &mut Vec<u8, BUF_SIZE>) {
-    let reading = block_on(devices::dht::read());
-    if let Some(reading) = reading {
+    // Pressure and temperature (hPa, °C) measured by the barometer
+    let mut station = None;
+    let mut sampled = false;
+    let wind_speed = devices::sensor::latest_measurement(Quantity::WindSpeed).map(|m| m.as_f32());
+
+    devices::sensor::latest(|reading| {
+        sampled = true;
+        let age = reading.age().as_secs();
+        match &reading.result {
+            Ok(measurements) => {
+                let find = |quantity| measurements.iter().find(|m| m.quantity == quantity);
+                if let (Some(pressure), Some(temperature)) =
+                    (find(Quantity::Pressure), find(Quantity::Temperature))
+                {
+                    station = Some((pressure.as_f32(), temperature.as_f32()));
+                }
+
+                for measurement in measurements {
+                    core::write!(
+                        buffer,
+                        "{} {}: {}",
+                        reading.sensor,
+                        measurement.quantity.name(),
+                        measurement
+                    )
+                    .unwrap();
+                    if measurement.raw != measurement.value {
+                        let raw = FixedPoint {
+                            value: measurement.raw,
+                            precision: measurement.precision,
+                        };
+                        core::write!(buffer, " (raw {})", raw).unwrap();
+                    }
+                    core::write!(buffer, " ({} s ago)<br>", age).unwrap();
+                }
+
+                if let (Some(temperature), Some(humidity)) = (
+                    find(Quantity::Temperature),
+                    find(Quantity::RelativeHumidity),
+                ) {
+                    write_derived(
+                        buffer,
+                        reading.sensor,
+                        temperature.as_f32(),
+                        humidity.as_f32(),
+                        wind_speed,
+                    );
+                }
+            }
+            Err(e) => {
+                core::write!(buffer, "{}: {} ({} s ago)<br>", reading.sensor, e, age).unwrap()
+            }
+        }
+    });
+    if !sampled {
+        core::write!(buffer, "No readings yet, try again<br>").unwrap();
+    }
+
+    if let Some((pressure, temperature)) = station {
+        let altitude = block_on(config::get()).altitude;
         core::write!(
             buffer,
-            "T: {} Rh: {}",
-            reading.get_temp(),
-            reading.get_hum()
+            "sea-level pressure: {:.1} hPa<br>",
+            meteo::sea_level_pressure(pressure, temperature, altitude as f32)
         )
         .unwrap();
     }
+
+    if let Some(tendency) = block_on(pressure::tendency()) {
+        core::write!(
+            buffer,
+            "pressure tendency: {} ({:+.1} hPa/3h, WMO code {})<br>",
+            tendency.trend,
+            tendency.change as f32 / 100.0,
+            tendency.characteristic
+        )
+        .unwrap();
+    }
+}
+
+fn write_derived<const BUF_SIZE: usize>(
+    buffer: &mut Vec<u8, BUF_SIZE>,
+    sensor: &str,
+    temperature: f32,
+    humidity: f32,
+    wind_speed: Option<f32>,
+) {
+    let dew_point = meteo::dew_point(temperature, humidity);
+    core::write!(buffer, "{} dew point: {:.1} °C<br>", sensor, dew_point).unwrap();
+    if dew_point < 0.0 {
+        let frost_point = meteo::frost_point(temperature, humidity);
+        core::write!(buffer, "{} frost point: {:.1} °C<br>", sensor, frost_point).unwrap();
+    }
+    core::write!(
+        buffer,
+        "{} heat index: {:.1} °C<br>{} humidex: {:.1}<br>{} absolute humidity: {:.1} g/m³<br>",
+        sensor,
+        meteo::heat_index(temperature, humidity),
+        sensor,
+        meteo::humidex(temperature, dew_point),
+        sensor,
+        meteo::absolute_humidity(temperature, humidity)
+    )
+    .unwrap();
+    if let Some(wind_chill) = wind_speed.and_then(|v| meteo::wind_chill(temperature, v)) {
+        core::write!(buffer, "{} wind chill: {:.1} °C<br>", sensor, wind_chill).unwrap();
+    }
+}
+
+/// One line per window, kept short as there are many series:
+/// `1 h: 18.2 (12:05) to 21.4 (12:45), mean 19.83 ± 0.61, n 120`
+fn write_summary<const BUF_SIZE: usize>(
+    buffer: &mut Vec<u8, BUF_SIZE>,
+    label: &str,
+    summary: &Summary,
+    precision: usize,
+) {
+    if summary.count == 0 {
+        core::write!(buffer, "{}: no data<br>", label).unwrap();
+        return;
+    }
+
+    let time_of_day = |t: devices::rtc::Timestamp| (t / 3600 % 24, t / 60 % 60);
+    let (min_hour, min_minute) = time_of_day(summary.min.1);
+    let (max_hour, max_minute) = time_of_day(summary.max.1);
+    core::write!(
+        buffer,
+        "{}: {:.p$} ({:02}:{:02}) to {:.p$} ({:02}:{:02}), mean {:.q$} ± {:.q$}, n {}<br>",
+        label,
+        summary.min.0,
+        min_hour,
+        min_minute,
+        summary.max.0,
+        max_hour,
+        max_minute,
+        summary.mean,
+        summary.stddev(),
+        summary.count,
+        p = precision,
+        q = precision + 1
+    )
+    .unwrap();
+}
+
+/// `GET /statistics`, a series at a time as all of them may not fit in one
+/// buffer
+pub struct StatisticsStream {
+    /// `None` until the clock is set, with nothing to show
+    now: Option<devices::rtc::Timestamp>,
+    next: usize,
+}
+
+impl StatisticsStream {
+    pub fn new(_parameters: Option<&KeyValueMap>) -> Result<Self, StatusCode> {
+        let now = block_on(devices::rtc::now()).map(|now| devices::rtc::to_timestamp(&now));
+        Ok(Self { now, next: 0 })
+    }
+}
+
+impl BodyStream for StatisticsStream {
+    fn content_type(&self) -> ContentType {
+        ContentType::TextHtml
+    }
+
+    fn fill<const N: usize>(&mut self, chunk: &mut Vec<u8, N>) -> bool {
+        let Some(now) = self.now else {
+            return false;
+        };
+
+        while chunk.capacity() - chunk.len() >= MAX_SERIES_HTML {
+            let Some(series) = statistics::get(now, self.next) else {
+                return false;
+            };
+            self.next += 1;
+
+            let precision = series.precision as usize;
+            core::write!(chunk, "{} {}", series.sensor, series.quantity.name()).unwrap();
+            match series.unit {
+                Unit::None => core::write!(chunk, "<br>").unwrap(),
+                unit => core::write!(chunk, " ({})<br>", unit.symbol()).unwrap(),
+            }
+            write_summary(chunk, "1 h", &series.last_hour, precision);
+            write_summary(chunk, "24 h", &series.last_day, precision);
+            write_summary(chunk, "today", &series.since_midnight, precision);
+        }
+        true
+    }
+}
+
+pub fn write_altitude<const BUF_SIZE: usize>(buffer: &mut Vec<u8, BUF_SIZE>) {
+    let config = block_on(config::get());
+    core::write!(buffer, "{} m", config.altitude).unwrap();
+}
+
+pub fn set_altitude(content: Option<&KeyValueMap>) -> Result<(), StatusCode> {
+    let content = content.ok_or(StatusCode::BadRequest)?;
+    if !authorized(content) {
+        return Err(StatusCode::Forbidden);
+    }
+
+    let altitude: i16 = content
+        .get_as("altitude")
+        .map_err(|_| StatusCode::BadRequest)?;
+    if !ALTITUDES.contains(&altitude) {
+        return Err(StatusCode::UnprocessableContent);
+    }
+
+    block_on(config::update(|config| config.altitude = altitude))
+        .map_err(|_| StatusCode::InternalServerError)
+}
+
+pub fn write_sample_interval<const BUF_SIZE: usize>(buffer: &mut Vec<u8, BUF_SIZE>) {
+    let config = block_on(config::get());
+    core::write!(buffer, "{} s", config.sample_interval).unwrap();
+}
+
+pub fn set_sample_interval(content: Option<&KeyValueMap>) -> Result<(), StatusCode> {
+    let content = content.ok_or(StatusCode::BadRequest)?;
+    if !authorized(content) {
+        return Err(StatusCode::Forbidden);
+    }
+
+    let interval: u16 = content
+        .get_as("interval")
+        .map_err(|_| StatusCode::BadRequest)?;
+    if !SAMPLE_INTERVALS.contains(&interval) {
+        return Err(StatusCode::UnprocessableContent);
+    }
+
+    block_on(config::update(|config| config.sample_interval = interval))
+        .map_err(|_| StatusCode::InternalServerError)
+}
+
+/// Whether the form carries the admin password, compared in constant time
+fn authorized(content: &KeyValueMap) -> bool {
+    let Ok(password) = content.get_str("password") else {
+        return false;
+    };
+    let expected = crate::ADMIN_PASSWORD.as_bytes();
+
+    password.len() == expected.len()
+        && password
+            .bytes()
+            .zip(expected)
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+pub fn write_calibration<const BUF_SIZE: usize>(buffer: &mut Vec<u8, BUF_SIZE>) {
+    let mut any = false;
+    calibration::for_each(|calibration| {
+        any = true;
+        core::write!(
+            buffer,
+            "{} {}: × {} + {}<br>",
+            calibration.sensor,
+            calibration.quantity.key(),
+            calibration.gain,
+            calibration.offset
+        )
+        .unwrap();
+    });
+    if !any {
+        core::write!(buffer, "No calibrations<br>").unwrap();
+    }
+}
+
+/// Calibrates a sensor's quantity either by gain and offset, or by two
+/// raw readings and their reference values. Gain 1 and offset 0 remove it.
+pub fn set_calibration(content: Option<&KeyValueMap>) -> Result<(), StatusCode> {
+    let content = content.ok_or(StatusCode::BadRequest)?;
+    if !authorized(content) {
+        return Err(StatusCode::Forbidden);
+    }
+
+    let sensor: String<16> = content
+        .get_as("sensor")
+        .map_err(|_| StatusCode::BadRequest)?;
+    let quantity = content
+        .get_str("quantity")
+        .ok()
+        .and_then(Quantity::from_key)
+        .ok_or(StatusCode::BadRequest)?;
+
+    let mut known = false;
+    devices::sensor::latest(|reading| known |= reading.sensor == sensor.as_str());
+    if !known {
+        return Err(StatusCode::UnprocessableContent);
+    }
+
+    let point = |raw, reference| -> Option<(f32, f32)> {
+        Some((content.get_as(raw).ok()?, content.get_as(reference).ok()?))
+    };
+    let calibration = match (point("raw1", "ref1"), point("raw2", "ref2")) {
+        (Some(low), Some(high)) => Calibration::two_point(sensor, quantity, low, high)
+            .ok_or(StatusCode::UnprocessableContent)?,
+        (None, None) => Calibration {
+            sensor,
+            quantity,
+            gain: content.get_as("gain").unwrap_or(1.0),
+            offset: content.get_as("offset").unwrap_or(0.0),
+        },
+        _ => return Err(StatusCode::BadRequest),
+    };
+    // Anything further off means a broken sensor rather than one to correct
+    if !(0.1..=10.0).contains(&calibration.gain) || !calibration.offset.is_finite() {
+        return Err(StatusCode::UnprocessableContent);
+    }
+
+    calibration::set(calibration).map_err(|e| match e {
+        CalibrationError::Full => StatusCode::UnprocessableContent,
+        CalibrationError::Flash => StatusCode::InternalServerError,
+    })
+}
+
+pub fn write_rain<const BUF_SIZE: usize>(buffer: &mut Vec<u8, BUF_SIZE>) {
+    let Some(rain) = block_on(devices::rain::accumulation()) else {
+        return;
+    };
+
+    // µm with three decimals are mm
+    for (period, amount) in [
+        ("last hour", rain.last_hour),
+        ("last 24 h", rain.last_day),
+        ("since midnight", rain.since_midnight),
+        ("total", rain.total),
+    ] {
+        core::write!(
+            buffer,
+            "{}: {}.{:03} mm<br>",
+            period,
+            amount / 1000,
+            amount % 1000
+        )
+        .unwrap();
+    }
+    core::write!(
+        buffer,
+        "rate: {}.{:03} mm/h<br>",
+        rain.rate / 1000,
+        rain.rate % 1000
+    )
+    .unwrap();
+}
+
+pub fn set_rain_calibration(content: Option<&KeyValueMap>) -> Result<(), StatusCode> {
+    let content = content.ok_or(StatusCode::BadRequest)?;
+    if !authorized(content) {
+        return Err(StatusCode::Forbidden);
+    }
+
+    let mm_per_tip: f32 = content
+        .get_as("mm_per_tip")
+        .map_err(|_| StatusCode::BadRequest)?;
+    if !MM_PER_TIP.contains(&mm_per_tip) {
+        return Err(StatusCode::UnprocessableContent);
+    }
+
+    let rain_per_tip = (mm_per_tip * 1000.0 + 0.5) as u16;
+    block_on(config::update(|config| config.rain_per_tip = rain_per_tip))
+        .map_err(|_| StatusCode::InternalServerError)
+}
+
+pub fn write_wind<const BUF_SIZE: usize>(buffer: &mut Vec<u8, BUF_SIZE>) {
+    let Some(wind) = block_on(devices::wind::wind()) else {
+        return;
+    };
+
+    // mm/s with three decimals are m/s
+    core::write!(
+        buffer,
+        "2 min mean: {}.{:03} m/s<br>10 min gust: {}.{:03} m/s<br>",
+        wind.speed / 1000,
+        wind.speed % 1000,
+        wind.gust / 1000,
+        wind.gust % 1000
+    )
+    .unwrap();
+    if let Some(direction) = wind.direction {
+        core::write!(
+            buffer,
+            "direction: {}° ({})<br>",
+            direction,
+            devices::wind::compass_point(direction)
+        )
+        .unwrap();
+    } else {
+        core::write!(buffer, "direction: calm<br>").unwrap();
+    }
+}
+
+pub fn set_wind_calibration(content: Option<&KeyValueMap>) -> Result<(), StatusCode> {
+    let content = content.ok_or(StatusCode::BadRequest)?;
+    if !authorized(content) {
+        return Err(StatusCode::Forbidden);
+    }
+
+    let ms_per_hz: f32 = content
+        .get_as("ms_per_hz")
+        .map_err(|_| StatusCode::BadRequest)?;
+    if !MS_PER_HZ.contains(&ms_per_hz) {
+        return Err(StatusCode::UnprocessableContent);
+    }
+
+    let wind_per_hz = (ms_per_hz * 1000.0 + 0.5) as u16;
+    block_on(config::update(|config| config.wind_per_hz = wind_per_hz))
+        .map_err(|_| StatusCode::InternalServerError)
+}
+
+pub fn write_diagnostics<const BUF_SIZE: usize>(buffer: &mut Vec<u8, BUF_SIZE>) {
+    let station_name = block_on(config::get()).station_name;
+    if !station_name.is_empty() {
+        core::write!(buffer, "station: {}<br>", Html(&station_name)).unwrap();
+    }
+    core::write!(buffer, "uptime: {} s<br>", Instant::now().as_secs()).unwrap();
+
+    let network = network::status();
+    match network.config {
+        Some(config) => core::write!(
+            buffer,
+            "address: {} ({})<br>",
+            config.address,
+            network.source.key()
+        )
+        .unwrap(),
+        None => core::write!(buffer, "address: none ({})<br>", network.source.key()).unwrap(),
+    }
+    let outages = network::outages();
+    core::write!(
+        buffer,
+        "network outages: {}, longest {} s<br>",
+        outages.count,
+        outages.longest.as_secs()
+    )
+    .unwrap();
+
+    match devices::system::chip_temperature() {
+        Some(temperature) => {
+            core::write!(buffer, "chip temperature: {:.1} °C<br>", temperature).unwrap()
+        }
+        None => core::write!(buffer, "chip temperature: unavailable<br>").unwrap(),
+    }
+
+    match devices::system::supply_voltage() {
+        Some(voltage) => core::write!(buffer, "VSYS: {:.2} V<br>", voltage).unwrap(),
+        None => core::write!(buffer, "VSYS: busy, try again<br>").unwrap(),
+    }
+
+    let now = block_on(devices::rtc::now())
+        .map(|now| devices::rtc::to_timestamp(&now))
+        .unwrap_or_default();
+    for tier in history::Tier::ALL {
+        match history::status(tier) {
+            Some(history::Status {
+                records,
+                capacity,
+                oldest: Some(oldest),
+                ..
+            }) => core::write!(
+                buffer,
+                "{} history: {} of {} records, over {} h<br>",
+                tier.key(),
+                records,
+                capacity,
+                now.saturating_sub(oldest) / 3600
+            )
+            .unwrap(),
+            Some(_) => core::write!(buffer, "{} history: empty<br>", tier.key()).unwrap(),
+            None => core::write!(buffer, "{} history: unavailable<br>", tier.key()).unwrap(),
+        }
+    }
+
+    devices::sensor::latest(|reading| {
+        let counters = &reading.counters;
+        core::write!(
+            buffer,
+            "{}: {} ok, {} no response, {} timeout, {} checksum, {} implausible, {} failed<br>",
+            reading.sensor,
+            counters.successes,
+            counters.no_response,
+            counters.timeout,
+            counters.checksum,
+            counters.implausible,
+            counters.read_failed
+        )
+        .unwrap();
+    });
 }
@@ -0,0 +1,464 @@
+//! JSON API under /api/v1
+
+use core::{fmt::Write, str::FromStr};
+
+use embassy_futures::block_on;
+use heapless::Vec;
+
+use super::Json;
+use crate::{
+    config,
+    devices::{
+        self,
+        rtc::Timestamp,
+        sensor::{Quantity, MAX_SENSORS, MAX_SERIES},
+    },
+    history::{self, Aggregate, Cursor, Entry, Tier},
+    http::{BodyStream, ContentType, GetStr, KeyValueMap, StatusCode},
+    network::{self, Source},
+    provisioning,
+};
+
+/// Room left in a chunk before writing one more object
+const MAX_OBJECT: usize = 192;
+
+/// Parses an optional parameter, failing only if present and invalid
+fn optional<T: FromStr>(
+    parameters: Option<&KeyValueMap>,
+    key: &str,
+) -> Result<Option<T>, StatusCode> {
+    match parameters.and_then(|p| p.get_str(key).ok()) {
+        None => Ok(None),
+        Some(value) => value.parse().map(Some).map_err(|_| StatusCode::BadRequest),
+    }
+}
+
+/// Name of a sensor currently registered, as stored in its readings
+fn sensor_name(name: &str) -> Option<&'static str> {
+    let mut found = None;
+    devices::sensor::latest(|reading| {
+        if reading.sensor == name {
+            found = Some(reading.sensor);
+        }
+    });
+    found
+}
+
+/// Measurements of one series within one step
+struct Bucket {
+    sensor: &'static str,
+    quantity: Quantity,
+    precision: u8,
+    count: u32,
+    sum: f32,
+    min: f32,
+    max: f32,
+}
+
+enum State {
+    Start,
+    Records,
+    /// Writing out the buckets of a step
+    Flush,
+    End,
+}
+
+/// `GET /api/v1/history?from=&to=&step=&sensor=&quantity=&tier=`: a JSON
+/// array of the logged measurements between `from` and `to` (seconds since
+/// 1970, station time). With `step` (seconds) they are reduced to the count,
+/// mean, min and max of every series per step. With `tier=hourly` or
+/// `daily` the stored aggregates are served, reaching back much further,
+/// with the rain fallen as `total`.
+pub struct HistoryStream {
+    tier: Tier,
+    cursor: Option<Cursor>,
+    from: Timestamp,
+    to: Timestamp,
+    step: u32,
+    sensor: Option<&'static str>,
+    quantity: Option<Quantity>,
+    /// Registered sensors, to name the logged measurements by
+    sensors: Vec<&'static str, MAX_SENSORS>,
+    state: State,
+    /// Start of the step being bucketed
+    start: Option<Timestamp>,
+    buckets: Vec<Bucket, MAX_SERIES>,
+    /// Entry of the next step, read before the buckets were written out
+    pending: Option<Entry>,
+    first: bool,
+}
+
+impl HistoryStream {
+    pub fn new(parameters: Option<&KeyValueMap>) -> Result<Self, StatusCode> {
+        let sensor = match parameters.and_then(|p| p.get_str("sensor").ok()) {
+            Some(name) => Some(sensor_name(name).ok_or(StatusCode::UnprocessableContent)?),
+            None => None,
+        };
+        let quantity = match parameters.and_then(|p| p.get_str("quantity").ok()) {
+            Some(key) => Some(Quantity::from_key(key).ok_or(StatusCode::UnprocessableContent)?),
+            None => None,
+        };
+
+        let tier = match parameters.and_then(|p| p.get_str("tier").ok()) {
+            Some(key) => Tier::from_key(key).ok_or(StatusCode::UnprocessableContent)?,
+            None => Tier::Raw,
+        };
+        let step = optional(parameters, "step")?.unwrap_or(0);
+        // Aggregates are not bucketed again
+        if tier != Tier::Raw && step != 0 {
+            return Err(StatusCode::UnprocessableContent);
+        }
+
+        let mut sensors = Vec::new();
+        devices::sensor::latest(|reading| {
+            // Cannot fail - at most as many readings as sensors
+            let _ = sensors.push(reading.sensor);
+        });
+
+        Ok(Self {
+            tier,
+            cursor: history::cursor(tier),
+            from: optional(parameters, "from")?.unwrap_or(0),
+            to: optional(parameters, "to")?.unwrap_or(Timestamp::MAX),
+            step,
+            sensor,
+            quantity,
+            sensors,
+            state: State::Start,
+            start: None,
+            buckets: Vec::new(),
+            pending: None,
+            first: true,
+        })
+    }
+
+    fn separator<const N: usize>(&mut self, chunk: &mut Vec<u8, N>) {
+        if !self.first {
+            chunk.push(b',').unwrap();
+        }
+        self.first = false;
+    }
+
+    /// The sensor of a record, if it was asked for
+    fn wanted<F: Fn(&str) -> bool>(
+        &self,
+        timestamp: Timestamp,
+        quantity: Quantity,
+        is_from: F,
+    ) -> Option<&'static str> {
+        if timestamp < self.from || timestamp > self.to {
+            return None;
+        }
+        if self.quantity.is_some_and(|q| q != quantity) {
+            return None;
+        }
+        match self.sensor {
+            Some(sensor) => is_from(sensor).then_some(sensor),
+            None => self.sensors.iter().copied().find(|s| is_from(s)),
+        }
+    }
+
+    fn write_entry<const N: usize>(&mut self, chunk: &mut Vec<u8, N>, sensor: &str, entry: &Entry) {
+        self.separator(chunk);
+        core::write!(
+            chunk,
+            "{{\"sensor\":\"{}\",\"quantity\":\"{}\",\"t\":{},\"value\":{:.p$}}}",
+            sensor,
+            entry.quantity.key(),
+            entry.timestamp,
+            entry.as_f32(),
+            p = entry.precision as usize
+        )
+        .unwrap();
+    }
+
+    fn write_bucket<const N: usize>(&mut self, chunk: &mut Vec<u8, N>, bucket: &Bucket) {
+        self.separator(chunk);
+        core::write!(
+            chunk,
+            concat!(
+                "{{\"sensor\":\"{}\",\"quantity\":\"{}\",\"t\":{},",
+                "\"n\":{},\"mean\":{:.q$},\"min\":{:.p$},\"max\":{:.p$}}}"
+            ),
+            bucket.sensor,
+            bucket.quantity.key(),
+            self.start.unwrap_or_default(),
+            bucket.count,
+            bucket.sum / bucket.count as f32,
+            bucket.min,
+            bucket.max,
+            p = bucket.precision as usize,
+            q = bucket.precision as usize + 1
+        )
+        .unwrap();
+    }
+
+    fn write_aggregate<const N: usize>(
+        &mut self,
+        chunk: &mut Vec<u8, N>,
+        sensor: &str,
+        aggregate: &Aggregate,
+    ) {
+        self.separator(chunk);
+        core::write!(
+            chunk,
+            concat!(
+                "{{\"sensor\":\"{}\",\"quantity\":\"{}\",\"t\":{},",
+                "\"n\":{},\"mean\":{:.p$},\"min\":{:.p$},\"max\":{:.p$}"
+            ),
+            sensor,
+            aggregate.quantity.key(),
+            aggregate.start,
+            aggregate.count,
+            aggregate.scale(aggregate.mean),
+            aggregate.scale(aggregate.min),
+            aggregate.scale(aggregate.max),
+            p = aggregate.precision as usize
+        )
+        .unwrap();
+        if aggregate.quantity == Quantity::Precipitation {
+            core::write!(
+                chunk,
+                ",\"total\":{:.p$}",
+                aggregate.scale(aggregate.total),
+                p = aggregate.precision as usize
+            )
+            .unwrap();
+        }
+        chunk.push(b'}').unwrap();
+    }
+
+    fn add(&mut self, sensor: &'static str, entry: &Entry) {
+        let value = entry.as_f32();
+        let bucket = self
+            .buckets
+            .iter_mut()
+            .find(|b| b.sensor == sensor && b.quantity == entry.quantity);
+        match bucket {
+            Some(bucket) => {
+                bucket.count += 1;
+                bucket.sum += value;
+                bucket.min = bucket.min.min(value);
+                bucket.max = bucket.max.max(value);
+            }
+            None => {
+                // More series than kept are dropped from the step
+                let _ = self.buckets.push(Bucket {
+                    sensor,
+                    quantity: entry.quantity,
+                    precision: entry.precision,
+                    count: 1,
+                    sum: value,
+                    min: value,
+                    max: value,
+                });
+            }
+        }
+    }
+}
+
+impl BodyStream for HistoryStream {
+    fn content_type(&self) -> ContentType {
+        ContentType::ApplicationJson
+    }
+
+    fn fill<const N: usize>(&mut self, chunk: &mut Vec<u8, N>) -> bool {
+        while chunk.capacity() - chunk.len() >= MAX_OBJECT {
+            match self.state {
+                State::Start => {
+                    chunk.push(b'[').unwrap();
+                    self.state = State::Records;
+                }
+                State::Records if self.tier != Tier::Raw => {
+                    let next = self
+                        .cursor
+                        .as_mut()
+                        .and_then(|cursor| history::next_aggregate(self.tier, cursor));
+                    let Some(aggregate) = next else {
+                        self.state = State::End;
+                        continue;
+                    };
+                    let sensor = self.wanted(aggregate.start, aggregate.quantity, |s| {
+                        aggregate.is_from(s)
+                    });
+                    if let Some(sensor) = sensor {
+                        self.write_aggregate(chunk, sensor, &aggregate);
+                    }
+                }
+                State::Records => {
+                    let next = match self.pending.take() {
+                        Some(entry) => Some(entry),
+                        None => self.cursor.as_mut().and_then(history::next),
+                    };
+                    let Some(entry) = next else {
+                        self.state = State::Flush;
+                        continue;
+                    };
+                    let Some(sensor) =
+                        self.wanted(entry.timestamp, entry.quantity, |s| entry.is_from(s))
+                    else {
+                        continue;
+                    };
+
+                    if self.step == 0 {
+                        self.write_entry(chunk, sensor, &entry);
+                        continue;
+                    }
+
+                    let start = entry.timestamp - entry.timestamp % self.step;
+                    if self.start != Some(start) && !self.buckets.is_empty() {
+                        self.pending = Some(entry);
+                        self.state = State::Flush;
+                        continue;
+                    }
+                    self.start = Some(start);
+                    self.add(sensor, &entry);
+                }
+                State::Flush => {
+                    if self.buckets.is_empty() {
+                        self.state = match self.pending {
+                            Some(_) => State::Records,
+                            None => State::End,
+                        };
+                    } else {
+                        let bucket = self.buckets.remove(0);
+                        self.write_bucket(chunk, &bucket);
+                    }
+                }
+                State::End => {
+                    chunk.push(b']').unwrap();
+                    return false;
+                }
+            }
+        }
+        true
+    }
+}
+
+/// `GET /api/v1/history/status`: capacity and span of every tier of the
+/// history, as a JSON object keyed by tier
+pub fn write_history_status<const BUF_SIZE: usize>(buffer: &mut Vec<u8, BUF_SIZE>) {
+    buffer.push(b'{').unwrap();
+    for (i, tier) in Tier::ALL.into_iter().enumerate() {
+        if i > 0 {
+            buffer.push(b',').unwrap();
+        }
+        core::write!(buffer, "\"{}\":", tier.key()).unwrap();
+        let Some(status) = history::status(tier) else {
+            core::write!(buffer, "null").unwrap();
+            continue;
+        };
+        core::write!(
+            buffer,
+            "{{\"capacity\":{},\"records\":{},\"oldest\":",
+            status.capacity,
+            status.records
+        )
+        .unwrap();
+        match status.oldest {
+            Some(oldest) => core::write!(buffer, "{}", oldest).unwrap(),
+            None => core::write!(buffer, "null").unwrap(),
+        }
+        core::write!(buffer, ",\"newest\":").unwrap();
+        match status.newest {
+            Some(newest) => core::write!(buffer, "{}}}", newest).unwrap(),
+            None => core::write!(buffer, "null}}").unwrap(),
+        }
+    }
+    buffer.push(b'}').unwrap();
+}
+
+/// `GET /api/v1/network`: the network joined and the address in use, with
+/// where it comes from, and the connection losses since boot in seconds
+pub fn write_network_status<const BUF_SIZE: usize>(buffer: &mut Vec<u8, BUF_SIZE>) {
+    let status = network::status();
+    let ssid = match status.source {
+        Source::AccessPoint => provisioning::SSID,
+        _ => status.ssid.as_str(),
+    };
+    core::write!(
+        buffer,
+        "{{\"ssid\":\"{}\",\"source\":\"{}\",",
+        Json(ssid),
+        status.source.key()
+    )
+    .unwrap();
+
+    let outages = network::outages();
+    core::write!(
+        buffer,
+        "\"outages\":{{\"count\":{},\"current\":",
+        outages.count
+    )
+    .unwrap();
+    match outages.since {
+        Some(since) => core::write!(buffer, "{}", since.elapsed().as_secs()).unwrap(),
+        None => core::write!(buffer, "null").unwrap(),
+    }
+    core::write!(
+        buffer,
+        ",\"last\":{},\"longest\":{},\"total\":{}}},",
+        outages.last.as_secs(),
+        outages.longest.as_secs(),
+        outages.total.as_secs()
+    )
+    .unwrap();
+
+    let Some(config) = status.config else {
+        core::write!(buffer, "\"address\":null,\"gateway\":null,\"dns\":[]}}").unwrap();
+        return;
+    };
+    core::write!(buffer, "\"address\":\"{}\",\"gateway\":", config.address).unwrap();
+    match config.gateway {
+        Some(gateway) => core::write!(buffer, "\"{}\"", gateway).unwrap(),
+        None => core::write!(buffer, "null").unwrap(),
+    }
+    core::write!(buffer, ",\"dns\":[").unwrap();
+    for (i, server) in config.dns_servers.iter().enumerate() {
+        if i > 0 {
+            buffer.push(b',').unwrap();
+        }
+        core::write!(buffer, "\"{}\"", server).unwrap();
+    }
+    core::write!(buffer, "]}}").unwrap();
+}
+
+/// `GET /api/v1/wifi/scan`: the access points seen in the last scan, made
+/// at startup and whenever rejoining, with its age in seconds
+pub fn write_wifi_scan<const BUF_SIZE: usize>(buffer: &mut Vec<u8, BUF_SIZE>) {
+    let scan = network::last_scan();
+    let network = block_on(config::get()).network;
+
+    core::write!(buffer, "{{\"age\":").unwrap();
+    match scan.at {
+        Some(at) => core::write!(buffer, "{}", at.elapsed().as_secs()).unwrap(),
+        None => core::write!(buffer, "null").unwrap(),
+    }
+    core::write!(buffer, ",\"networks\":[").unwrap();
+    for (i, visible) in scan.networks.iter().enumerate() {
+        if i > 0 {
+            buffer.push(b',').unwrap();
+        }
+        let [a, b, c, d, e, f] = visible.bssid;
+        core::write!(
+            buffer,
+            concat!(
+                "{{\"ssid\":\"{}\",\"bssid\":\"{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}\",",
+                "\"channel\":{},\"rssi\":{},\"security\":\"{}\",\"known\":{}}}"
+            ),
+            Json(&visible.ssid),
+            a,
+            b,
+            c,
+            d,
+            e,
+            f,
+            visible.channel,
+            visible.rssi,
+            if visible.secured { "secured" } else { "open" },
+            network.all().any(|known| known.ssid == visible.ssid)
+        )
+        .unwrap();
+    }
+    core::write!(buffer, "]}}").unwrap();
+}
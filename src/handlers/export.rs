@@ -0,0 +1,201 @@
+//! Downloads of the logged history for analysis tools
+
+use core::fmt::Write;
+
+use heapless::Vec;
+
+use crate::{
+    devices::{
+        self,
+        rtc::{self, Iso8601, Timestamp},
+        sensor::{Quantity, Unit},
+    },
+    history::{self, Cursor, Entry, Tier},
+    http::{BodyStream, ContentType, GetStr, KeyValueMap, StatusCode},
+};
+
+/// Sensor and quantity pairs exported, enough for all sensors' measurements
+const MAX_COLUMNS: usize = 16;
+/// Room left in a chunk before writing one more row
+const MAX_ROW: usize = 64 + MAX_COLUMNS * 40;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    Ndjson,
+}
+
+struct Column {
+    sensor: &'static str,
+    quantity: Quantity,
+    unit: Unit,
+}
+
+impl Column {
+    /// Name with the unit, like `dht22 temperature (°C)`
+    fn write_name<const N: usize>(&self, chunk: &mut Vec<u8, N>) {
+        core::write!(chunk, "{} {}", self.sensor, self.quantity.key()).unwrap();
+        if self.unit != Unit::None {
+            core::write!(chunk, " ({})", self.unit.symbol()).unwrap();
+        }
+    }
+}
+
+enum State {
+    Header,
+    Rows,
+    End,
+}
+
+/// `GET /export.csv` and `/export.ndjson`, optionally `?from=&to=` with
+/// inclusive ISO 8601 dates: the logged measurements with one row, or
+/// object, per sample time and one column per sensor and quantity
+pub struct ExportStream {
+    format: Format,
+    cursor: Option<Cursor>,
+    from: Timestamp,
+    to: Timestamp,
+    columns: Vec<Column, MAX_COLUMNS>,
+    state: State,
+    /// Time and values of the row being assembled
+    time: Option<Timestamp>,
+    row: [Option<(f32, u8)>; MAX_COLUMNS],
+}
+
+impl ExportStream {
+    pub fn new(format: Format, parameters: Option<&KeyValueMap>) -> Result<Self, StatusCode> {
+        // Forms send empty fields left blank
+        let date = |key| match parameters.and_then(|p| p.get_str(key).ok()) {
+            Some("") | None => Ok(None),
+            Some(date) => rtc::parse_date(date)
+                .map(Some)
+                .ok_or(StatusCode::BadRequest),
+        };
+        let from = date("from")?.unwrap_or(0);
+        let to = date("to")?.map_or(Timestamp::MAX, |to| to + 24 * 60 * 60 - 1);
+
+        // The quantities the sensors measure now make up the columns
+        let mut columns = Vec::new();
+        devices::sensor::latest(|reading| {
+            for measurement in reading.result.iter().flatten() {
+                let column = Column {
+                    sensor: reading.sensor,
+                    quantity: measurement.quantity,
+                    unit: measurement.unit,
+                };
+                // More quantities than columns are left out
+                let _ = columns.push(column);
+            }
+        });
+
+        Ok(Self {
+            format,
+            cursor: history::cursor(Tier::Raw),
+            from,
+            to,
+            columns,
+            state: State::Header,
+            time: None,
+            row: [None; MAX_COLUMNS],
+        })
+    }
+
+    fn write_header<const N: usize>(&self, chunk: &mut Vec<u8, N>) {
+        if self.format == Format::Ndjson {
+            return;
+        }
+
+        core::write!(chunk, "time").unwrap();
+        for column in &self.columns {
+            core::write!(chunk, ",").unwrap();
+            column.write_name(chunk);
+        }
+        writeln!(chunk).unwrap();
+    }
+
+    /// Writes out and clears the row being assembled
+    fn write_row<const N: usize>(&mut self, chunk: &mut Vec<u8, N>) {
+        let Some(time) = self.time.take() else {
+            return;
+        };
+
+        match self.format {
+            Format::Csv => core::write!(chunk, "{}", Iso8601(time)).unwrap(),
+            Format::Ndjson => core::write!(chunk, "{{\"time\":\"{}\"", Iso8601(time)).unwrap(),
+        }
+        for (column, value) in self.columns.iter().zip(self.row.iter_mut()) {
+            match (self.format, value.take()) {
+                (Format::Csv, Some((value, precision))) => {
+                    core::write!(chunk, ",{:.p$}", value, p = precision as usize).unwrap()
+                }
+                (Format::Csv, None) => core::write!(chunk, ",").unwrap(),
+                (Format::Ndjson, Some((value, precision))) => {
+                    core::write!(chunk, ",\"").unwrap();
+                    column.write_name(chunk);
+                    core::write!(chunk, "\":{:.p$}", value, p = precision as usize).unwrap();
+                }
+                (Format::Ndjson, None) => {}
+            }
+        }
+        match self.format {
+            Format::Csv => writeln!(chunk).unwrap(),
+            Format::Ndjson => writeln!(chunk, "}}").unwrap(),
+        }
+    }
+
+    fn add(&mut self, entry: &Entry) {
+        let column = self
+            .columns
+            .iter()
+            .position(|c| c.quantity == entry.quantity && entry.is_from(c.sensor));
+        if let Some(column) = column {
+            self.time = Some(entry.timestamp);
+            self.row[column] = Some((entry.as_f32(), entry.precision));
+        }
+    }
+}
+
+impl BodyStream for ExportStream {
+    fn content_type(&self) -> ContentType {
+        match self.format {
+            Format::Csv => ContentType::TextCsv,
+            Format::Ndjson => ContentType::ApplicationNdjson,
+        }
+    }
+
+    fn attachment(&self) -> Option<&'static str> {
+        match self.format {
+            Format::Csv => Some("weather.csv"),
+            Format::Ndjson => Some("weather.ndjson"),
+        }
+    }
+
+    fn fill<const N: usize>(&mut self, chunk: &mut Vec<u8, N>) -> bool {
+        while chunk.capacity() - chunk.len() >= MAX_ROW {
+            match self.state {
+                State::Header => {
+                    self.write_header(chunk);
+                    self.state = State::Rows;
+                }
+                State::Rows => {
+                    let Some(entry) = self.cursor.as_mut().and_then(history::next) else {
+                        self.write_row(chunk);
+                        self.state = State::End;
+                        continue;
+                    };
+                    if entry.timestamp < self.from || entry.timestamp > self.to {
+                        continue;
+                    }
+
+                    // Measurements logged together share their timestamp
+                    if self.time.is_some_and(|t| t != entry.timestamp) {
+                        self.write_row(chunk);
+                    }
+                    self.add(&entry);
+                }
+                State::End => return false,
+            }
+        }
+        true
+    }
+}
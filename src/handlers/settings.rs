@@ -0,0 +1,796 @@
+//! The settings page, `/config`, and its API, `/api/v1/config`. Both need
+//! the admin password, also for viewing, which shows no secrets. Changes on
+//! the page are applied once the listed ones are confirmed.
+//!
+//! In setup mode, `/setup` asks for just the network to join.
+
+use core::{
+    fmt::{self, Display, Write},
+    net::Ipv4Addr,
+    ops::RangeInclusive,
+    str::FromStr,
+};
+
+use embassy_futures::block_on;
+use embassy_net::Ipv4Cidr;
+use heapless::{String, Vec};
+
+use super::{
+    authorized, Html, Json, ALTITUDES, LOG_INTERVALS, MM_PER_TIP, MS_PER_HZ, SAMPLE_INTERVALS,
+};
+use crate::{
+    calibration,
+    config::{self, Config, IpMode, KnownNetwork, MAX_KNOWN},
+    devices::{self, sensor::FixedPoint},
+    http::{GetStr, KeyValueMap, StatusCode},
+    network, provisioning,
+};
+
+/// Minutes of outage before rebooting, up to a day
+const OUTAGE_REBOOTS: RangeInclusive<u16> = 0..=24 * 60;
+/// Of the known networks in reach, the one of the highest is joined
+const PRIORITIES: RangeInclusive<u8> = 0..=9;
+
+/// A gateway, or its absence
+struct Gateway(Option<Ipv4Addr>);
+
+impl Display for Gateway {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(gateway) => core::write!(f, "{}", gateway),
+            None => f.write_str("none"),
+        }
+    }
+}
+
+/// µm or mm/s per unit, shown in mm or m/s
+fn thousandths(value: u16) -> FixedPoint {
+    FixedPoint {
+        value: value as i32,
+        precision: 3,
+    }
+}
+
+fn parse<T: FromStr>(value: &str) -> Result<T, StatusCode> {
+    value.parse().map_err(|_| StatusCode::BadRequest)
+}
+
+fn within<T: PartialOrd>(value: T, range: &RangeInclusive<T>) -> Result<T, StatusCode> {
+    if range.contains(&value) {
+        Ok(value)
+    } else {
+        Err(StatusCode::UnprocessableContent)
+    }
+}
+
+fn text<const N: usize>(value: &str) -> Result<String<N>, StatusCode> {
+    String::try_from(value).map_err(|_| StatusCode::UnprocessableContent)
+}
+
+/// Applies the settings present and not blank in a form to `config`, all
+/// of them valid or none
+fn apply(content: &KeyValueMap, config: &Config) -> Result<Config, StatusCode> {
+    let field = |key: &str| content.get_str(key).ok().filter(|value| !value.is_empty());
+    let mut new = config.clone();
+
+    if let Some(name) = field("station_name") {
+        new.station_name = text(name)?;
+    }
+    if let Some(altitude) = field("altitude") {
+        new.altitude = within(parse(altitude)?, &ALTITUDES)?;
+    }
+    if let Some(interval) = field("sample_interval") {
+        new.sample_interval = within(parse(interval)?, &SAMPLE_INTERVALS)?;
+    }
+    if let Some(interval) = field("log_interval") {
+        new.log_interval = within(parse(interval)?, &LOG_INTERVALS)?;
+    }
+    if let Some(mm_per_tip) = field("mm_per_tip") {
+        let mm_per_tip: f32 = within(parse(mm_per_tip)?, &MM_PER_TIP)?;
+        new.rain_per_tip = (mm_per_tip * 1000.0 + 0.5) as u16;
+    }
+    if let Some(ms_per_hz) = field("ms_per_hz") {
+        let ms_per_hz: f32 = within(parse(ms_per_hz)?, &MS_PER_HZ)?;
+        new.wind_per_hz = (ms_per_hz * 1000.0 + 0.5) as u16;
+    }
+    if let Some(minutes) = field("outage_reboot") {
+        new.outage_reboot = within(parse(minutes)?, &OUTAGE_REBOOTS)?;
+    }
+
+    let network = &mut new.network;
+    if let Some(ssid) = field("ssid") {
+        network.ssid = text(ssid)?;
+    }
+    // WPA2 takes 8 to 63 characters, or 64 hex digits of the key itself
+    if let Some(password) = field("wifi_password") {
+        within(password.len(), &(8..=64))?;
+        network.password = text(password)?;
+    }
+    if let Some(mode) = field("ip_mode") {
+        network.mode = IpMode::from_key(mode).ok_or(StatusCode::UnprocessableContent)?;
+    }
+    if let Some(address) = field("address") {
+        let address: Ipv4Cidr = parse(address)?;
+        within(address.prefix_len(), &(1..=30))?;
+        network.address = address;
+    }
+    if let Some(gateway) = field("gateway") {
+        network.gateway = Some(parse(gateway)?);
+    }
+    if let Some(priority) = field("priority") {
+        network.priority = within(parse(priority)?, &PRIORITIES)?;
+    }
+
+    // The other known networks, numbered from 1. A blank network removes
+    // one, a new one without passphrase is open.
+    let mut known: [Option<KnownNetwork>; MAX_KNOWN] =
+        core::array::from_fn(|i| network.known.get(i).cloned());
+    for (i, slot) in known.iter_mut().enumerate() {
+        let key = |name: &str| {
+            let mut key = String::<16>::new();
+            // Cannot fail - at most "known3_password"
+            let _ = core::write!(key, "known{}_{}", i + 1, name);
+            key
+        };
+        if let Ok(ssid) = content.get_str(&key("ssid")) {
+            if ssid.is_empty() {
+                *slot = None;
+            } else {
+                let slot = slot.get_or_insert_with(|| KnownNetwork {
+                    ssid: String::new(),
+                    password: String::new(),
+                    priority: 0,
+                });
+                slot.ssid = text(ssid)?;
+            }
+        }
+        let Some(slot) = slot else {
+            continue;
+        };
+        if let Some(password) = field(&key("password")) {
+            within(password.len(), &(8..=64))?;
+            slot.password = text(password)?;
+        }
+        if let Some(priority) = field(&key("priority")) {
+            slot.priority = within(parse(priority)?, &PRIORITIES)?;
+        }
+    }
+    network.known = known.into_iter().flatten().collect();
+    if network
+        .gateway
+        .is_some_and(|gateway| !network.address.contains_addr(&gateway))
+    {
+        return Err(StatusCode::UnprocessableContent);
+    }
+
+    Ok(new)
+}
+
+fn ssid(known: Option<&KnownNetwork>) -> &str {
+    known.map_or("(none)", |known| known.ssid.as_str())
+}
+
+/// Calls `f` with the name, old and new value of every setting changed
+fn for_each_change<F: FnMut(&str, &dyn Display, &dyn Display)>(
+    old: &Config,
+    new: &Config,
+    mut f: F,
+) {
+    let mut change = |name, old: &dyn Display, new: &dyn Display, changed| {
+        if changed {
+            f(name, old, new);
+        }
+    };
+
+    change(
+        "station name",
+        &old.station_name,
+        &new.station_name,
+        old.station_name != new.station_name,
+    );
+    change(
+        "altitude (m)",
+        &old.altitude,
+        &new.altitude,
+        old.altitude != new.altitude,
+    );
+    change(
+        "sample interval (s)",
+        &old.sample_interval,
+        &new.sample_interval,
+        old.sample_interval != new.sample_interval,
+    );
+    change(
+        "log interval (s)",
+        &old.log_interval,
+        &new.log_interval,
+        old.log_interval != new.log_interval,
+    );
+    change(
+        "rain per tip (mm)",
+        &thousandths(old.rain_per_tip),
+        &thousandths(new.rain_per_tip),
+        old.rain_per_tip != new.rain_per_tip,
+    );
+    change(
+        "wind per Hz (m/s)",
+        &thousandths(old.wind_per_hz),
+        &thousandths(new.wind_per_hz),
+        old.wind_per_hz != new.wind_per_hz,
+    );
+
+    change(
+        "reboot after outage (min)",
+        &old.outage_reboot,
+        &new.outage_reboot,
+        old.outage_reboot != new.outage_reboot,
+    );
+
+    let (old, new) = (&old.network, &new.network);
+    change("WiFi network", &old.ssid, &new.ssid, old.ssid != new.ssid);
+    change(
+        "WiFi passphrase",
+        &"(hidden)",
+        &"(hidden)",
+        old.password != new.password,
+    );
+    change(
+        "IP mode",
+        &old.mode.key(),
+        &new.mode.key(),
+        old.mode != new.mode,
+    );
+    change(
+        "static address",
+        &old.address,
+        &new.address,
+        old.address != new.address,
+    );
+    change(
+        "gateway",
+        &Gateway(old.gateway),
+        &Gateway(new.gateway),
+        old.gateway != new.gateway,
+    );
+    change(
+        "WiFi priority",
+        &old.priority,
+        &new.priority,
+        old.priority != new.priority,
+    );
+
+    for i in 0..MAX_KNOWN {
+        let (old, new) = (old.known.get(i), new.known.get(i));
+        let mut name = String::<32>::new();
+        // Cannot fail - short enough
+        let _ = core::write!(name, "known network {}", i + 1);
+        if ssid(old) != ssid(new) {
+            f(&name, &ssid(old), &ssid(new));
+        }
+        let (Some(old), Some(new)) = (old, new) else {
+            continue;
+        };
+        let length = name.len();
+        if old.password != new.password {
+            let _ = name.push_str(" passphrase");
+            f(&name, &"(hidden)", &"(hidden)");
+            name.truncate(length);
+        }
+        if old.priority != new.priority {
+            let _ = name.push_str(" priority");
+            f(&name, &old.priority, &new.priority);
+        }
+    }
+}
+
+/// Whether a form field is the admin password or a WiFi passphrase
+fn is_secret(key: &str) -> bool {
+    key.ends_with("password")
+}
+
+/// Only the network settings need a reboot to apply
+fn needs_reboot(old: &Config, new: &Config) -> bool {
+    old.network != new.network
+}
+
+/// `GET /config`: the admin password, to see the settings
+pub fn write_config_login<const BUF_SIZE: usize>(buffer: &mut Vec<u8, BUF_SIZE>) {
+    core::write!(
+        buffer,
+        concat!(
+            "<!DOCTYPE html><html><head><title>Settings</title></head><body>",
+            "<form action='/config/view' method='POST'>",
+            "<input type='password' name='password'> admin password<br>",
+            "<input type='submit' value='Show settings'><br>",
+            "</form></body></html>"
+        )
+    )
+    .unwrap();
+}
+
+/// `POST /config/view`: a form with the current settings
+pub fn write_config<const BUF_SIZE: usize>(
+    content: Option<&KeyValueMap>,
+    buffer: &mut Vec<u8, BUF_SIZE>,
+) -> StatusCode {
+    if !content.is_some_and(authorized) {
+        write_error(buffer, StatusCode::Forbidden);
+        return StatusCode::Forbidden;
+    }
+
+    let config = block_on(config::get());
+    let network = &config.network;
+
+    core::write!(
+        buffer,
+        concat!(
+            "<!DOCTYPE html><html><head><title>Settings</title></head><body>",
+            "<form action='/config' method='POST'>",
+            "<input type='text' name='station_name' value='{}'> station name<br>",
+            "<input type='number' name='altitude' value='{}'> m altitude<br>",
+            "<input type='number' name='sample_interval' value='{}'> s between readings<br>",
+            "<input type='number' name='log_interval' value='{}'> s between logged readings<br>",
+            "<input type='number' step='0.001' name='mm_per_tip' value='{}'> mm per tip<br>",
+            "<input type='number' step='0.001' name='ms_per_hz' value='{}'> m/s per Hz<br>",
+            "<input type='text' name='ssid' value='{}'> WiFi network<br>",
+            "<input type='password' name='wifi_password'> WiFi passphrase, blank keeps it<br>",
+            "<select name='ip_mode'>",
+            "<option value='static'{}>static address</option>",
+            "<option value='dhcp'{}>DHCP</option>",
+            "</select><br>",
+            "<input type='text' name='address' value='{}'> static address/prefix<br>",
+            "<input type='text' name='gateway' value='"
+        ),
+        Html(&config.station_name),
+        config.altitude,
+        config.sample_interval,
+        config.log_interval,
+        thousandths(config.rain_per_tip),
+        thousandths(config.wind_per_hz),
+        Html(&network.ssid),
+        if network.mode == IpMode::Static {
+            " selected"
+        } else {
+            ""
+        },
+        if network.mode == IpMode::Dhcp {
+            " selected"
+        } else {
+            ""
+        },
+        network.address,
+    )
+    .unwrap();
+    if let Some(gateway) = network.gateway {
+        core::write!(buffer, "{}", gateway).unwrap();
+    }
+    core::write!(
+        buffer,
+        concat!(
+            "'> gateway<br>",
+            "<input type='number' name='outage_reboot' value='{}'> ",
+            "min without network before rebooting, 0 never<br>",
+            "<input type='password' name='password'> admin password<br>",
+            "<input type='submit' value='Review changes'><br>",
+            "</form>",
+        ),
+        config.outage_reboot
+    )
+    .unwrap();
+
+    write_known_form(buffer, network);
+    write_reboot_form(buffer);
+    core::write!(
+        buffer,
+        "Sensor calibrations are set on the <a href='/'>main page</a>.</body></html>"
+    )
+    .unwrap();
+
+    StatusCode::Ok
+}
+
+/// The networks joined instead of the main one when in reach, in a form of
+/// its own for the fields a request can have
+fn write_known_form<const BUF_SIZE: usize>(
+    buffer: &mut Vec<u8, BUF_SIZE>,
+    network: &config::Network,
+) {
+    core::write!(
+        buffer,
+        concat!(
+            "<form action='/config' method='POST'>",
+            "Of the networks in reach, the one of the highest priority is joined.<br>",
+            "<input type='number' name='priority' value='{}'> priority of {}<br>"
+        ),
+        network.priority,
+        Html(&network.ssid)
+    )
+    .unwrap();
+    for i in 0..MAX_KNOWN {
+        let known = network.known.get(i);
+        core::write!(
+            buffer,
+            concat!(
+                "<input type='text' name='known{0}_ssid' value='{1}'> network {0}, blank for none<br>",
+                "<input type='password' name='known{0}_password'> its passphrase, blank keeps it<br>",
+                "<input type='number' name='known{0}_priority' value='{2}'> its priority<br>"
+            ),
+            i + 1,
+            Html(known.map_or("", |k| k.ssid.as_str())),
+            known.map_or(0, |k| k.priority)
+        )
+        .unwrap();
+    }
+    core::write!(
+        buffer,
+        concat!(
+            "<input type='password' name='password'> admin password<br>",
+            "<input type='submit' value='Review changes'><br>",
+            "</form>"
+        )
+    )
+    .unwrap();
+}
+
+fn write_reboot_form<const BUF_SIZE: usize>(buffer: &mut Vec<u8, BUF_SIZE>) {
+    core::write!(
+        buffer,
+        concat!(
+            "<form action='/config/reboot' method='POST'>",
+            "<input type='password' name='password'> admin password<br>",
+            "<input type='submit' value='Reboot'><br>",
+            "</form>"
+        )
+    )
+    .unwrap();
+}
+
+fn write_error<const BUF_SIZE: usize>(buffer: &mut Vec<u8, BUF_SIZE>, status_code: StatusCode) {
+    let reason = match status_code {
+        StatusCode::Forbidden => "Wrong admin password",
+        StatusCode::BadRequest => "A setting could not be read",
+        StatusCode::UnprocessableContent => "A setting is out of range",
+        _ => "Saving the settings failed",
+    };
+    core::write!(buffer, "{}. <a href='/config'>back</a>", reason).unwrap();
+}
+
+/// `POST /config`: lists the changes a form makes along with a form to
+/// confirm them, which applies them
+pub fn set_config<const BUF_SIZE: usize>(
+    content: Option<&KeyValueMap>,
+    buffer: &mut Vec<u8, BUF_SIZE>,
+) -> StatusCode {
+    let Some(content) = content else {
+        write_error(buffer, StatusCode::BadRequest);
+        return StatusCode::BadRequest;
+    };
+    if !authorized(content) {
+        write_error(buffer, StatusCode::Forbidden);
+        return StatusCode::Forbidden;
+    }
+
+    let old = block_on(config::get());
+    let new = match apply(content, &old) {
+        Ok(new) => new,
+        Err(status_code) => {
+            write_error(buffer, status_code);
+            return status_code;
+        }
+    };
+
+    let confirmed = content.get_str("confirm").is_ok();
+    if confirmed && block_on(config::update(|config| *config = new.clone())).is_err() {
+        write_error(buffer, StatusCode::InternalServerError);
+        return StatusCode::InternalServerError;
+    }
+
+    core::write!(
+        buffer,
+        "<!DOCTYPE html><html><head><title>Settings</title></head><body><ul>"
+    )
+    .unwrap();
+    let mut any = false;
+    for_each_change(&old, &new, |name, old, new| {
+        any = true;
+        core::write!(
+            buffer,
+            "<li>{}: {} → {}</li>",
+            Html(name),
+            Html(old),
+            Html(new)
+        )
+        .unwrap();
+    });
+    core::write!(buffer, "</ul>").unwrap();
+
+    if !any {
+        core::write!(buffer, "No changes. ").unwrap();
+    } else if !confirmed {
+        // The same form again, confirmed. Passwords are not sent back but
+        // asked for again.
+        core::write!(buffer, "<form action='/config' method='POST'>").unwrap();
+        for (key, value) in content.iter() {
+            if !is_secret(key) {
+                core::write!(
+                    buffer,
+                    "<input type='hidden' name='{}' value='{}'>",
+                    Html(key),
+                    Html(value)
+                )
+                .unwrap();
+            } else if key != "password" && !value.is_empty() {
+                core::write!(buffer, "<input type='password' name='{}'> ", Html(key)).unwrap();
+                match key
+                    .strip_prefix("known")
+                    .and_then(|key| key.strip_suffix("_password"))
+                {
+                    Some(i) => core::write!(buffer, "passphrase of network {}", Html(i)),
+                    None => core::write!(buffer, "WiFi passphrase"),
+                }
+                .unwrap();
+                core::write!(buffer, " again<br>").unwrap();
+            }
+        }
+        core::write!(
+            buffer,
+            concat!(
+                "<input type='password' name='password'> admin password<br>",
+                "<input type='hidden' name='confirm' value='1'>",
+                "<input type='submit' value='Apply'></form>"
+            )
+        )
+        .unwrap();
+    } else if needs_reboot(&old, &new) {
+        core::write!(buffer, "Saved. The network settings apply after a reboot.").unwrap();
+        write_reboot_form(buffer);
+    } else {
+        core::write!(buffer, "Saved. ").unwrap();
+    }
+    core::write!(buffer, "<a href='/config'>back</a></body></html>").unwrap();
+
+    StatusCode::Ok
+}
+
+/// `POST /config/reboot`
+pub fn reboot(content: Option<&KeyValueMap>) -> Result<(), StatusCode> {
+    let content = content.ok_or(StatusCode::BadRequest)?;
+    if !authorized(content) {
+        return Err(StatusCode::Forbidden);
+    }
+
+    devices::system::schedule_reboot();
+    Ok(())
+}
+
+/// `GET /api/v1/config?password=`: the settings as JSON, the WiFi
+/// passphrases left out
+pub fn write_config_json<const BUF_SIZE: usize>(
+    parameters: Option<&KeyValueMap>,
+    buffer: &mut Vec<u8, BUF_SIZE>,
+) -> Result<(), StatusCode> {
+    if !parameters.is_some_and(authorized) {
+        return Err(StatusCode::Forbidden);
+    }
+
+    let config = block_on(config::get());
+    let network = &config.network;
+
+    core::write!(
+        buffer,
+        concat!(
+            "{{\"station_name\":\"{}\",\"altitude\":{},\"sample_interval\":{},",
+            "\"log_interval\":{},\"mm_per_tip\":{},\"ms_per_hz\":{},",
+            "\"network\":{{\"ssid\":\"{}\",\"ip_mode\":\"{}\",",
+            "\"address\":\"{}\",\"gateway\":\"{}\",\"priority\":{},\"known\":["
+        ),
+        Json(&config.station_name),
+        config.altitude,
+        config.sample_interval,
+        config.log_interval,
+        thousandths(config.rain_per_tip),
+        thousandths(config.wind_per_hz),
+        Json(&network.ssid),
+        network.mode.key(),
+        network.address,
+        Gateway(network.gateway),
+        network.priority,
+    )
+    .unwrap();
+    for (i, known) in network.known.iter().enumerate() {
+        if i > 0 {
+            buffer.push(b',').unwrap();
+        }
+        core::write!(
+            buffer,
+            "{{\"ssid\":\"{}\",\"priority\":{}}}",
+            Json(&known.ssid),
+            known.priority
+        )
+        .unwrap();
+    }
+    core::write!(
+        buffer,
+        "]}},\"outage_reboot\":{},\"calibrations\":[",
+        config.outage_reboot
+    )
+    .unwrap();
+
+    let mut first = true;
+    calibration::for_each(|calibration| {
+        if !first {
+            buffer.push(b',').unwrap();
+        }
+        first = false;
+        core::write!(
+            buffer,
+            "{{\"sensor\":\"{}\",\"quantity\":\"{}\",\"gain\":{},\"offset\":{}}}",
+            Json(&calibration.sensor),
+            calibration.quantity.key(),
+            calibration.gain,
+            calibration.offset
+        )
+        .unwrap();
+    });
+    core::write!(buffer, "]}}").unwrap();
+
+    Ok(())
+}
+
+/// `POST /api/v1/config`: the changes a JSON object of settings makes, keyed
+/// as the page's form fields, e.g. `{"altitude": 120, "password": "..."}`.
+/// They are applied with `"confirm": true`, and otherwise only checked.
+pub fn set_config_json<const BUF_SIZE: usize>(
+    content: Option<&KeyValueMap>,
+    buffer: &mut Vec<u8, BUF_SIZE>,
+) -> Result<(), StatusCode> {
+    let content = content.ok_or(StatusCode::BadRequest)?;
+    if !authorized(content) {
+        return Err(StatusCode::Forbidden);
+    }
+
+    let old = block_on(config::get());
+    let new = apply(content, &old)?;
+    let confirmed = content.get_str("confirm") == Ok("true");
+    if confirmed {
+        block_on(config::update(|config| *config = new.clone()))
+            .map_err(|_| StatusCode::InternalServerError)?;
+    }
+
+    core::write!(buffer, "{{\"changes\":[").unwrap();
+    let mut first = true;
+    for_each_change(&old, &new, |name, old, new| {
+        if !first {
+            buffer.push(b',').unwrap();
+        }
+        first = false;
+        // Cannot fail - the longest values, names of 32 characters, fit
+        let (mut old_text, mut new_text) = (String::<64>::new(), String::<64>::new());
+        let _ = core::write!(old_text, "{}", old);
+        let _ = core::write!(new_text, "{}", new);
+        core::write!(
+            buffer,
+            "{{\"setting\":\"{}\",\"from\":\"{}\",\"to\":\"{}\"}}",
+            name,
+            Json(&old_text),
+            Json(&new_text)
+        )
+        .unwrap();
+    });
+    core::write!(
+        buffer,
+        "],\"applied\":{},\"reboot\":{}}}",
+        confirmed,
+        needs_reboot(&old, &new)
+    )
+    .unwrap();
+
+    Ok(())
+}
+
+/// `GET /setup`, and every unknown path in setup mode: a form for the
+/// network to join
+pub fn write_setup<const BUF_SIZE: usize>(buffer: &mut Vec<u8, BUF_SIZE>) {
+    let config = block_on(config::get());
+    let network = &config.network;
+
+    core::write!(
+        buffer,
+        concat!(
+            "<!DOCTYPE html><html><head><title>Setup</title>",
+            "<meta name='viewport' content='width=device-width'></head><body>",
+            "<p>{} could not join {}.</p>",
+            "<form action='/setup' method='POST'>",
+            "<input type='text' name='ssid' value='{}' list='networks'> WiFi network<br>",
+            "<input type='password' name='wifi_password'> WiFi passphrase, blank keeps it<br>",
+            "<select name='ip_mode'>",
+            "<option value='static'{}>static address</option>",
+            "<option value='dhcp'{}>DHCP</option>",
+            "</select><br>",
+            "<input type='text' name='address' value='{}'> static address/prefix<br>",
+            "<input type='password' name='password'> admin password<br>",
+            "<input type='submit' value='Join'><br>",
+            "</form>",
+            "Everything else is on the <a href='/config'>settings page</a>."
+        ),
+        Html(if config.station_name.is_empty() {
+            "The station"
+        } else {
+            config.station_name.as_str()
+        }),
+        Html(&network.ssid),
+        Html(&network.ssid),
+        if network.mode == IpMode::Static {
+            " selected"
+        } else {
+            ""
+        },
+        if network.mode == IpMode::Dhcp {
+            " selected"
+        } else {
+            ""
+        },
+        network.address,
+    )
+    .unwrap();
+
+    // The networks seen before giving up, to pick from
+    let scan = network::last_scan();
+    core::write!(buffer, "<datalist id='networks'>").unwrap();
+    for (i, visible) in scan.networks.iter().enumerate() {
+        if scan.networks[..i].iter().any(|v| v.ssid == visible.ssid) {
+            continue;
+        }
+        core::write!(buffer, "<option value='{}'>", Html(&visible.ssid)).unwrap();
+    }
+    core::write!(buffer, "</datalist></body></html>").unwrap();
+}
+
+/// `POST /setup`: saves the network settings and reboots to join it
+pub fn set_setup<const BUF_SIZE: usize>(
+    content: Option<&KeyValueMap>,
+    buffer: &mut Vec<u8, BUF_SIZE>,
+) -> StatusCode {
+    let result = content.ok_or(StatusCode::BadRequest).and_then(|content| {
+        if !authorized(content) {
+            return Err(StatusCode::Forbidden);
+        }
+        let new = apply(content, &block_on(config::get()))?;
+        block_on(config::update(|config| *config = new.clone()))
+            .map_err(|_| StatusCode::InternalServerError)?;
+        Ok(new.network)
+    });
+    let network = match result {
+        Ok(network) => network,
+        Err(status_code) => {
+            let reason = match status_code {
+                StatusCode::Forbidden => "Wrong admin password",
+                StatusCode::UnprocessableContent => "A setting is out of range",
+                StatusCode::InternalServerError => "Saving the settings failed",
+                _ => "A setting could not be read",
+            };
+            core::write!(buffer, "{}. <a href='/setup'>back</a>", reason).unwrap();
+            return status_code;
+        }
+    };
+
+    core::write!(
+        buffer,
+        "Joining {}. Once back on it, the station is at ",
+        Html(&network.ssid)
+    )
+    .unwrap();
+    match network.mode {
+        IpMode::Static => core::write!(buffer, "{}.", network.address.address()).unwrap(),
+        IpMode::Dhcp => core::write!(buffer, "the address its DHCP server gives.").unwrap(),
+    }
+    core::write!(
+        buffer,
+        " Should it fail to join, {} comes back.",
+        provisioning::SSID
+    )
+    .unwrap();
+
+    devices::system::schedule_reboot();
+    StatusCode::Ok
+}
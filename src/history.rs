@@ -0,0 +1,417 @@
+//! Measurement history kept in ring logs in flash, surviving reboots. The
+//! raw measurements are rolled up into hourly and daily aggregates as they
+//! are logged, which are kept far longer.
+
+mod ring;
+mod rollup;
+
+use core::cell::RefCell;
+
+use defmt::{info, warn, Format};
+use embassy_rp::flash::Error;
+use embassy_sync::blocking_mutex::{raw::ThreadModeRawMutex, Mutex};
+
+use crate::devices::{
+    flash::Partition,
+    rtc::Timestamp,
+    sensor::{Measurement, Quantity},
+};
+use ring::RingLog;
+use rollup::{Rollup, DAY, HOUR};
+
+pub use ring::Cursor;
+
+const ENTRY_SIZE: usize = 12;
+const AGGREGATE_SIZE: usize = 28;
+
+/// Resolutions the history is kept at
+#[derive(Format, Clone, Copy, PartialEq, Eq)]
+pub enum Tier {
+    Raw,
+    Hourly,
+    Daily,
+}
+
+impl Tier {
+    pub const ALL: [Tier; 3] = [Tier::Raw, Tier::Hourly, Tier::Daily];
+
+    pub fn key(&self) -> &'static str {
+        match self {
+            Tier::Raw => "raw",
+            Tier::Hourly => "hourly",
+            Tier::Daily => "daily",
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|t| t.key() == key)
+    }
+}
+
+/// A logged measurement
+#[derive(Clone, Copy)]
+pub struct Entry {
+    pub timestamp: Timestamp,
+    /// Hash of the sensor's name
+    sensor: u16,
+    pub quantity: Quantity,
+    pub value: i32,
+    pub precision: u8,
+}
+
+impl Entry {
+    fn encode(&self) -> [u8; ENTRY_SIZE] {
+        let mut payload = [0; ENTRY_SIZE];
+        payload[..4].copy_from_slice(&self.timestamp.to_le_bytes());
+        payload[4..8].copy_from_slice(&self.value.to_le_bytes());
+        payload[8] = quantity_index(self.quantity);
+        payload[9] = self.precision;
+        payload[10..].copy_from_slice(&self.sensor.to_le_bytes());
+        payload
+    }
+
+    fn decode(payload: &[u8; ENTRY_SIZE]) -> Option<Self> {
+        Some(Self {
+            timestamp: u32::from_le_bytes(payload[..4].try_into().ok()?),
+            value: i32::from_le_bytes(payload[4..8].try_into().ok()?),
+            quantity: *Quantity::ALL.get(payload[8] as usize)?,
+            precision: payload[9],
+            sensor: u16::from_le_bytes(payload[10..].try_into().ok()?),
+        })
+    }
+
+    pub fn is_from(&self, sensor: &str) -> bool {
+        self.sensor == sensor_tag(sensor)
+    }
+
+    pub fn as_f32(&self) -> f32 {
+        to_f32(self.value, self.precision)
+    }
+}
+
+/// Measurements of a series over an hour or a day
+#[derive(Clone, Copy)]
+pub struct Aggregate {
+    /// Start of the period
+    pub start: Timestamp,
+    /// Hash of the sensor's name
+    sensor: u16,
+    pub quantity: Quantity,
+    pub precision: u8,
+    pub count: u16,
+    pub min: i32,
+    pub max: i32,
+    pub mean: i32,
+    /// Increase over the period of accumulating quantities, i.e. rain fallen
+    pub total: i32,
+}
+
+impl Aggregate {
+    fn encode(&self) -> [u8; AGGREGATE_SIZE] {
+        let mut payload = [0; AGGREGATE_SIZE];
+        payload[..4].copy_from_slice(&self.start.to_le_bytes());
+        payload[4..6].copy_from_slice(&self.sensor.to_le_bytes());
+        payload[6] = quantity_index(self.quantity);
+        payload[7] = self.precision;
+        payload[8..10].copy_from_slice(&self.count.to_le_bytes());
+        // Two bytes reserved
+        payload[12..16].copy_from_slice(&self.min.to_le_bytes());
+        payload[16..20].copy_from_slice(&self.max.to_le_bytes());
+        payload[20..24].copy_from_slice(&self.mean.to_le_bytes());
+        payload[24..].copy_from_slice(&self.total.to_le_bytes());
+        payload
+    }
+
+    fn decode(payload: &[u8; AGGREGATE_SIZE]) -> Option<Self> {
+        Some(Self {
+            start: u32::from_le_bytes(payload[..4].try_into().ok()?),
+            sensor: u16::from_le_bytes(payload[4..6].try_into().ok()?),
+            quantity: *Quantity::ALL.get(payload[6] as usize)?,
+            precision: payload[7],
+            count: u16::from_le_bytes(payload[8..10].try_into().ok()?),
+            min: i32::from_le_bytes(payload[12..16].try_into().ok()?),
+            max: i32::from_le_bytes(payload[16..20].try_into().ok()?),
+            mean: i32::from_le_bytes(payload[20..24].try_into().ok()?),
+            total: i32::from_le_bytes(payload[24..].try_into().ok()?),
+        })
+    }
+
+    pub fn is_from(&self, sensor: &str) -> bool {
+        self.sensor == sensor_tag(sensor)
+    }
+
+    pub fn scale(&self, value: i32) -> f32 {
+        to_f32(value, self.precision)
+    }
+}
+
+fn quantity_index(quantity: Quantity) -> u8 {
+    Quantity::ALL
+        .iter()
+        .position(|q| *q == quantity)
+        .unwrap_or_default() as u8
+}
+
+fn to_f32(value: i32, precision: u8) -> f32 {
+    let mut value = value as f32;
+    for _ in 0..precision {
+        value /= 10.0;
+    }
+    value
+}
+
+/// FNV-1a hash of a sensor's name folded to 16 bits, telling the few
+/// sensors of a station apart in two bytes
+fn sensor_tag(name: &str) -> u16 {
+    let hash = name.bytes().fold(0x811c_9dc5u32, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+    });
+    (hash >> 16) as u16 ^ hash as u16
+}
+
+type AggregateLog = RingLog<Partition, AGGREGATE_SIZE>;
+
+struct Logs {
+    raw: RingLog<Partition, ENTRY_SIZE>,
+    hourly: AggregateLog,
+    daily: AggregateLog,
+    rollup: Rollup,
+}
+
+/// Appends an aggregate to the log of its tier
+fn append(log: &mut AggregateLog, aggregate: &Aggregate) {
+    if let Err(e) = log.append(&aggregate.encode()) {
+        warn!("Writing history failed: {}", e);
+    }
+}
+
+impl Logs {
+    fn open() -> Result<Self, Error> {
+        Ok(Self {
+            raw: RingLog::new(Partition::RAW, *b"RLOG")?,
+            hourly: RingLog::new(Partition::HOURLY, *b"RLOH")?,
+            daily: RingLog::new(Partition::DAILY, *b"RLOD")?,
+            rollup: Rollup::default(),
+        })
+    }
+
+    /// Rebuilds the periods in progress before the reboot from the hours and
+    /// the raw measurements not yet aggregated, writing out those that ended
+    fn resume(&mut self) -> Result<(), Error> {
+        let Self {
+            raw,
+            hourly,
+            daily,
+            rollup,
+        } = self;
+
+        let mut last_day = None;
+        daily.for_each(|payload| {
+            if let Some(aggregate) = Aggregate::decode(payload) {
+                last_day = Some(aggregate.start);
+            }
+        })?;
+
+        let mut last_hour = None;
+        hourly.for_each(|payload| {
+            let Some(aggregate) = Aggregate::decode(payload) else {
+                return;
+            };
+            last_hour = Some(aggregate.start);
+            if !matches!(last_day, Some(day) if aggregate.start < day + DAY) {
+                rollup.add_hour(&aggregate, |_, day| append(daily, day));
+            }
+        })?;
+
+        raw.for_each(|payload| {
+            let Some(entry) = Entry::decode(payload) else {
+                return;
+            };
+            if matches!(last_hour, Some(hour) if entry.timestamp < hour + HOUR) {
+                rollup.skip(&entry);
+            } else {
+                rollup.add(&entry, |tier, aggregate| match tier {
+                    Tier::Hourly => append(hourly, aggregate),
+                    _ => append(daily, aggregate),
+                });
+            }
+        })
+    }
+
+    fn aggregates(&mut self, tier: Tier) -> Option<&mut AggregateLog> {
+        match tier {
+            Tier::Raw => None,
+            Tier::Hourly => Some(&mut self.hourly),
+            Tier::Daily => Some(&mut self.daily),
+        }
+    }
+}
+
+/// Size and span of a tier
+pub struct Status {
+    /// Records the tier holds when full
+    pub capacity: u32,
+    pub records: u32,
+    pub oldest: Option<Timestamp>,
+    pub newest: Option<Timestamp>,
+}
+
+static LOGS: Mutex<ThreadModeRawMutex, RefCell<Option<Logs>>> = Mutex::new(RefCell::new(None));
+
+/// Opens the logs, picking up after the last records written before reboot.
+/// Needs the flash initialized.
+pub fn init() {
+    match Logs::open() {
+        Ok(mut logs) => {
+            if let Err(e) = logs.resume() {
+                warn!("Resuming history aggregates failed: {}", e);
+            }
+            LOGS.lock(|cell| cell.replace(Some(logs)));
+        }
+        Err(e) => warn!("Opening history failed: {}", e),
+    }
+
+    for tier in Tier::ALL {
+        if let Some(status) = status(tier) {
+            info!("{} history holds {} records", tier.key(), status.records);
+        }
+    }
+}
+
+/// Appends a timestamped measurement of `sensor` to the log, and to the
+/// aggregates of its hour and day
+pub fn record(sensor: &str, measurement: &Measurement) {
+    let Some(timestamp) = measurement.timestamp else {
+        return;
+    };
+    let entry = Entry {
+        timestamp,
+        sensor: sensor_tag(sensor),
+        quantity: measurement.quantity,
+        value: measurement.value,
+        precision: measurement.precision,
+    };
+
+    LOGS.lock(|cell| {
+        let mut logs = cell.borrow_mut();
+        let Some(Logs {
+            raw,
+            hourly,
+            daily,
+            rollup,
+        }) = logs.as_mut()
+        else {
+            return;
+        };
+
+        if let Err(e) = raw.append(&entry.encode()) {
+            warn!("Writing history failed: {}", e);
+        }
+        rollup.add(&entry, |tier, aggregate| match tier {
+            Tier::Hourly => append(hourly, aggregate),
+            _ => append(daily, aggregate),
+        });
+    });
+}
+
+/// A cursor at the oldest record of a tier
+pub fn cursor(tier: Tier) -> Option<Cursor> {
+    LOGS.lock(|cell| {
+        let logs = cell.borrow();
+        let logs = logs.as_ref()?;
+        Some(match tier {
+            Tier::Raw => logs.raw.cursor(),
+            Tier::Hourly => logs.hourly.cursor(),
+            Tier::Daily => logs.daily.cursor(),
+        })
+    })
+}
+
+/// The next logged measurement after a raw tier `cursor`, advancing it
+pub fn next(cursor: &mut Cursor) -> Option<Entry> {
+    LOGS.lock(|cell| {
+        let mut logs = cell.borrow_mut();
+        let log = &mut logs.as_mut()?.raw;
+        loop {
+            match log.next(cursor) {
+                Ok(Some(payload)) => {
+                    if let Some(entry) = Entry::decode(&payload) {
+                        return Some(entry);
+                    }
+                }
+                Ok(None) => return None,
+                Err(e) => {
+                    warn!("Reading history failed: {}", e);
+                    return None;
+                }
+            }
+        }
+    })
+}
+
+/// The next aggregate after a `cursor` of an aggregate tier, advancing it
+pub fn next_aggregate(tier: Tier, cursor: &mut Cursor) -> Option<Aggregate> {
+    LOGS.lock(|cell| {
+        let mut logs = cell.borrow_mut();
+        let log = logs.as_mut()?.aggregates(tier)?;
+        loop {
+            match log.next(cursor) {
+                Ok(Some(payload)) => {
+                    if let Some(aggregate) = Aggregate::decode(&payload) {
+                        return Some(aggregate);
+                    }
+                }
+                Ok(None) => return None,
+                Err(e) => {
+                    warn!("Reading history failed: {}", e);
+                    return None;
+                }
+            }
+        }
+    })
+}
+
+/// Counts the records of a tier, `None` if the history is unavailable
+pub fn status(tier: Tier) -> Option<Status> {
+    LOGS.lock(|cell| {
+        let mut logs = cell.borrow_mut();
+        let logs = logs.as_mut()?;
+
+        let mut status = Status {
+            capacity: match tier {
+                Tier::Raw => logs.raw.capacity(),
+                Tier::Hourly => logs.hourly.capacity(),
+                Tier::Daily => logs.daily.capacity(),
+            },
+            records: 0,
+            oldest: None,
+            newest: None,
+        };
+        let mut count = |timestamp| {
+            status.records += 1;
+            status.oldest = status.oldest.or(Some(timestamp));
+            status.newest = Some(timestamp);
+        };
+        let result = match tier {
+            Tier::Raw => logs.raw.for_each(|payload| {
+                if let Some(entry) = Entry::decode(payload) {
+                    count(entry.timestamp);
+                }
+            }),
+            Tier::Hourly | Tier::Daily => {
+                // Cannot fail - an aggregate tier
+                let log = logs.aggregates(tier)?;
+                log.for_each(|payload| {
+                    if let Some(aggregate) = Aggregate::decode(payload) {
+                        count(aggregate.start);
+                    }
+                })
+            }
+        };
+        if let Err(e) = result {
+            warn!("Reading history failed: {}", e);
+            return None;
+        }
+        Some(status)
+    })
+}
@@ -0,0 +1,394 @@
+//! Append-only log of fixed-size records in a ring of flash sectors.
+//!
+//! Every sector starts with a header holding a sequence number, so that the
+//! newest sector can be found after a reboot. Sectors are filled and erased
+//! in turn, spreading wear evenly. Each record carries a CRC: a record torn
+//! by a power loss fails it and is skipped, and the log continues after it.
+
+use embedded_storage::nor_flash::NorFlash;
+
+use crate::crc::crc32;
+
+/// Magic, sequence number, 4 reserved bytes and CRC
+const HEADER_SIZE: usize = 16;
+const CRC_SIZE: usize = 4;
+const ERASED: u8 = 0xff;
+
+#[derive(Clone, Copy)]
+struct Head {
+    sector: u32,
+    sequence: u32,
+    /// Next free record slot in the sector
+    slot: u32,
+}
+
+/// Position of a reader in the log
+#[derive(Clone, Copy)]
+pub struct Cursor {
+    /// The oldest sector when the reader started
+    first: Option<u32>,
+    /// Sectors read since the first
+    sector: u32,
+    slot: u32,
+}
+
+/// A log of `P` byte payloads, each stored with its CRC
+pub struct RingLog<F: NorFlash, const P: usize> {
+    flash: F,
+    /// Tells the log's sectors from those of other logs or older formats
+    magic: u32,
+    sectors: u32,
+    /// Where the next record goes, `None` while the log is empty
+    head: Option<Head>,
+}
+
+impl<F: NorFlash, const P: usize> RingLog<F, P> {
+    const RECORD_SIZE: usize = P + CRC_SIZE;
+    const SLOTS: u32 = ((F::ERASE_SIZE - HEADER_SIZE) / Self::RECORD_SIZE) as u32;
+
+    /// Opens the log spanning all of `flash`, finding where it left off
+    pub fn new(flash: F, magic: [u8; 4]) -> Result<Self, F::Error> {
+        assert!(P.is_multiple_of(F::WRITE_SIZE) && HEADER_SIZE.is_multiple_of(F::WRITE_SIZE));
+
+        let sectors = (flash.capacity() / F::ERASE_SIZE) as u32;
+        let mut log = Self {
+            flash,
+            magic: u32::from_le_bytes(magic),
+            sectors,
+            head: None,
+        };
+        log.recover()?;
+        Ok(log)
+    }
+
+    fn sector_offset(&self, sector: u32) -> u32 {
+        sector * F::ERASE_SIZE as u32
+    }
+
+    fn slot_offset(&self, sector: u32, slot: u32) -> u32 {
+        self.sector_offset(sector) + (HEADER_SIZE + slot as usize * Self::RECORD_SIZE) as u32
+    }
+
+    /// Records the log holds when full, less a sector about to be reused
+    pub fn capacity(&self) -> u32 {
+        (self.sectors - 1) * Self::SLOTS
+    }
+
+    /// Reads the record in a slot as its payload and stored CRC
+    fn read_slot(&mut self, sector: u32, slot: u32) -> Result<([u8; P], u32), F::Error> {
+        let offset = self.slot_offset(sector, slot);
+        let mut payload = [0; P];
+        let mut crc = [0; CRC_SIZE];
+        self.flash.read(offset, &mut payload)?;
+        self.flash.read(offset + P as u32, &mut crc)?;
+        Ok((payload, u32::from_le_bytes(crc)))
+    }
+
+    /// Sequence number of a sector, if it has a valid header
+    fn sequence(&mut self, sector: u32) -> Result<Option<u32>, F::Error> {
+        let mut header = [0; HEADER_SIZE];
+        self.flash.read(self.sector_offset(sector), &mut header)?;
+
+        let valid = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) == self.magic
+            && u32::from_le_bytes([header[12], header[13], header[14], header[15]])
+                == crc32(&header[..12]);
+        Ok(valid.then_some(u32::from_le_bytes([
+            header[4], header[5], header[6], header[7],
+        ])))
+    }
+
+    fn recover(&mut self) -> Result<(), F::Error> {
+        let mut newest: Option<(u32, u32)> = None;
+        for sector in 0..self.sectors {
+            match (self.sequence(sector)?, newest) {
+                (Some(sequence), Some((_, newest_sequence))) if sequence <= newest_sequence => {}
+                (Some(sequence), _) => newest = Some((sector, sequence)),
+                (None, _) => {}
+            }
+        }
+
+        let Some((sector, sequence)) = newest else {
+            self.head = None;
+            return Ok(());
+        };
+
+        // Slots are filled in order, so the first erased one is the next free
+        let mut slot = 0;
+        while slot < Self::SLOTS {
+            let (payload, crc) = self.read_slot(sector, slot)?;
+            if payload.iter().all(|b| *b == ERASED) && crc == u32::MAX {
+                break;
+            }
+            slot += 1;
+        }
+
+        self.head = Some(Head {
+            sector,
+            sequence,
+            slot,
+        });
+        Ok(())
+    }
+
+    /// Erases a sector and marks it as the newest
+    fn start_sector(&mut self, sector: u32, sequence: u32) -> Result<(), F::Error> {
+        let offset = self.sector_offset(sector);
+        self.flash.erase(offset, offset + F::ERASE_SIZE as u32)?;
+
+        let mut header = [ERASED; HEADER_SIZE];
+        header[..4].copy_from_slice(&self.magic.to_le_bytes());
+        header[4..8].copy_from_slice(&sequence.to_le_bytes());
+        let crc = crc32(&header[..12]);
+        header[12..].copy_from_slice(&crc.to_le_bytes());
+        self.flash.write(offset, &header)?;
+
+        self.head = Some(Head {
+            sector,
+            sequence,
+            slot: 0,
+        });
+        Ok(())
+    }
+
+    pub fn append(&mut self, payload: &[u8; P]) -> Result<(), F::Error> {
+        match self.head {
+            None => self.start_sector(0, 0)?,
+            Some(head) if head.slot >= Self::SLOTS => self.start_sector(
+                (head.sector + 1) % self.sectors,
+                head.sequence.wrapping_add(1),
+            )?,
+            Some(_) => {}
+        }
+        // Cannot fail - a sector was just started if there was none
+        let Some(head) = self.head.as_mut() else {
+            return Ok(());
+        };
+
+        let (sector, slot) = (head.sector, head.slot);
+        // Move on even if the write fails, the slot may be partly written
+        head.slot += 1;
+        let offset = self.slot_offset(sector, slot);
+        self.flash.write(offset, payload)?;
+        self.flash
+            .write(offset + P as u32, &crc32(payload).to_le_bytes())
+    }
+
+    /// A cursor at the oldest record
+    pub fn cursor(&self) -> Cursor {
+        Cursor {
+            first: self.head.map(|head| (head.sector + 1) % self.sectors),
+            sector: 0,
+            slot: 0,
+        }
+    }
+
+    /// The next intact record at or after `cursor`, advancing it
+    pub fn next(&mut self, cursor: &mut Cursor) -> Result<Option<[u8; P]>, F::Error> {
+        let (Some(first), Some(head)) = (cursor.first, self.head) else {
+            return Ok(None);
+        };
+
+        while cursor.sector < self.sectors {
+            let sector = (first + cursor.sector) % self.sectors;
+            // The sector after the newest is the oldest, unless never used
+            if cursor.slot == 0 && self.sequence(sector)?.is_none() {
+                cursor.sector += 1;
+                continue;
+            }
+
+            let slots = if sector == head.sector {
+                head.slot
+            } else {
+                Self::SLOTS
+            };
+            while cursor.slot < slots {
+                let (payload, crc) = self.read_slot(sector, cursor.slot)?;
+                cursor.slot += 1;
+
+                if crc == crc32(&payload) {
+                    return Ok(Some(payload));
+                }
+            }
+
+            // Records appended later are picked up by the next call
+            if sector == head.sector {
+                return Ok(None);
+            }
+            cursor.sector += 1;
+            cursor.slot = 0;
+        }
+        Ok(None)
+    }
+
+    /// Calls `f` with every intact record, oldest first
+    pub fn for_each<G: FnMut(&[u8; P])>(&mut self, mut f: G) -> Result<(), F::Error> {
+        let mut cursor = self.cursor();
+        while let Some(payload) = self.next(&mut cursor)? {
+            f(&payload);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_storage::nor_flash::{ErrorType, NorFlashErrorKind, ReadNorFlash};
+
+    use super::*;
+
+    const SECTOR_SIZE: usize = 256;
+    const SECTORS: usize = 3;
+    /// Records per sector, the header taking the first 16 bytes
+    const SLOTS: u64 = ((SECTOR_SIZE - HEADER_SIZE) / (8 + CRC_SIZE)) as u64;
+
+    /// Flash in memory, with writes clearing bits only and erases setting
+    /// whole sectors
+    struct MockFlash {
+        memory: [u8; SECTORS * SECTOR_SIZE],
+        /// Bytes written before a write is cut short, as by a power loss
+        torn_after: Option<usize>,
+    }
+
+    impl MockFlash {
+        fn new() -> Self {
+            Self {
+                memory: [ERASED; SECTORS * SECTOR_SIZE],
+                torn_after: None,
+            }
+        }
+    }
+
+    impl ErrorType for MockFlash {
+        type Error = NorFlashErrorKind;
+    }
+
+    impl ReadNorFlash for MockFlash {
+        const READ_SIZE: usize = 1;
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+            let offset = offset as usize;
+            let memory = self
+                .memory
+                .get(offset..offset + bytes.len())
+                .ok_or(NorFlashErrorKind::OutOfBounds)?;
+            bytes.copy_from_slice(memory);
+            Ok(())
+        }
+
+        fn capacity(&self) -> usize {
+            self.memory.len()
+        }
+    }
+
+    impl NorFlash for MockFlash {
+        const WRITE_SIZE: usize = 4;
+        const ERASE_SIZE: usize = SECTOR_SIZE;
+
+        fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+            let (from, to) = (from as usize, to as usize);
+            if !from.is_multiple_of(SECTOR_SIZE) || !to.is_multiple_of(SECTOR_SIZE) {
+                return Err(NorFlashErrorKind::NotAligned);
+            }
+            self.memory
+                .get_mut(from..to)
+                .ok_or(NorFlashErrorKind::OutOfBounds)?
+                .fill(ERASED);
+            Ok(())
+        }
+
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+            let offset = offset as usize;
+            if !offset.is_multiple_of(Self::WRITE_SIZE)
+                || !bytes.len().is_multiple_of(Self::WRITE_SIZE)
+            {
+                return Err(NorFlashErrorKind::NotAligned);
+            }
+            let memory = self
+                .memory
+                .get_mut(offset..offset + bytes.len())
+                .ok_or(NorFlashErrorKind::OutOfBounds)?;
+
+            let written = self.torn_after.map_or(bytes.len(), |n| n.min(bytes.len()));
+            for (cell, byte) in memory.iter_mut().zip(&bytes[..written]) {
+                *cell &= byte;
+            }
+            if written < bytes.len() {
+                return Err(NorFlashErrorKind::Other);
+            }
+            Ok(())
+        }
+    }
+
+    type Log<'a> = RingLog<&'a mut MockFlash, 8>;
+
+    fn open(flash: &mut MockFlash) -> Log<'_> {
+        RingLog::new(flash, *b"TEST").unwrap()
+    }
+
+    fn records(log: &mut Log) -> Vec<u64> {
+        let mut records = Vec::new();
+        log.for_each(|payload| records.push(u64::from_le_bytes(*payload)))
+            .unwrap();
+        records
+    }
+
+    #[test]
+    fn empty_log_has_no_records() {
+        let mut flash = MockFlash::new();
+        assert_eq!(records(&mut open(&mut flash)), []);
+    }
+
+    #[test]
+    fn wraps_around_keeping_the_newest() {
+        let mut flash = MockFlash::new();
+        let mut log = open(&mut flash);
+        assert_eq!(log.capacity() as u64, (SECTORS as u64 - 1) * SLOTS);
+
+        // The first sector is reused for the last 5
+        let count = SECTORS as u64 * SLOTS + 5;
+        for i in 0..count {
+            log.append(&i.to_le_bytes()).unwrap();
+        }
+        let newest: Vec<u64> = (SLOTS..count).collect();
+        assert_eq!(records(&mut log), newest);
+
+        // After a reboot, the log carries on where it left off
+        let mut log = open(&mut flash);
+        assert_eq!(records(&mut log), newest);
+        log.append(&count.to_le_bytes()).unwrap();
+        assert_eq!(records(&mut log), (SLOTS..=count).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn recovers_after_torn_write() {
+        let mut flash = MockFlash::new();
+        let mut log = open(&mut flash);
+        for i in 0..3u64 {
+            log.append(&i.to_le_bytes()).unwrap();
+        }
+
+        // Power lost half way through the payload, before its CRC
+        flash.torn_after = Some(4);
+        assert!(open(&mut flash).append(&3u64.to_le_bytes()).is_err());
+        flash.torn_after = None;
+
+        let mut log = open(&mut flash);
+        assert_eq!(records(&mut log), [0, 1, 2]);
+        log.append(&4u64.to_le_bytes()).unwrap();
+        assert_eq!(records(&mut log), [0, 1, 2, 4]);
+    }
+
+    #[test]
+    fn skips_record_with_bad_crc() {
+        let mut flash = MockFlash::new();
+        let mut log = open(&mut flash);
+        for i in 0..3u64 {
+            log.append(&i.to_le_bytes()).unwrap();
+        }
+
+        // A bit of the second payload flipped
+        flash.memory[HEADER_SIZE + 8 + CRC_SIZE] ^= 0x01;
+        let mut log = open(&mut flash);
+        assert_eq!(records(&mut log), [0, 2]);
+    }
+}
@@ -0,0 +1,218 @@
+//! Hourly and daily aggregates of the logged measurements, built up as they
+//! are logged and written out once their period is over
+
+use heapless::Vec;
+
+use super::{Aggregate, Entry, Tier};
+use crate::devices::{
+    rtc::Timestamp,
+    sensor::{Quantity, MAX_SERIES},
+};
+
+pub const HOUR: u32 = 60 * 60;
+pub const DAY: u32 = 24 * HOUR;
+
+fn start_of(timestamp: Timestamp, period: u32) -> Timestamp {
+    timestamp - timestamp % period
+}
+
+/// Measurements of one series in one period, as fixed-point values
+#[derive(Clone, Copy)]
+struct Accumulator {
+    start: Timestamp,
+    count: u32,
+    sum: i64,
+    min: i32,
+    max: i32,
+    total: i32,
+}
+
+impl Accumulator {
+    const fn new(start: Timestamp) -> Self {
+        Self {
+            start,
+            count: 0,
+            sum: 0,
+            min: i32::MAX,
+            max: i32::MIN,
+            total: 0,
+        }
+    }
+
+    /// Picks up an aggregate written before, its sum rebuilt from the mean
+    fn from_aggregate(aggregate: &Aggregate) -> Self {
+        Self {
+            start: aggregate.start,
+            count: aggregate.count as u32,
+            sum: aggregate.mean as i64 * aggregate.count as i64,
+            min: aggregate.min,
+            max: aggregate.max,
+            total: aggregate.total,
+        }
+    }
+
+    fn add(&mut self, value: i32, increase: i32) {
+        self.count += 1;
+        self.sum += value as i64;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.total = self.total.saturating_add(increase);
+    }
+
+    fn merge(&mut self, other: &Self) {
+        self.count += other.count;
+        self.sum += other.sum;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.total = self.total.saturating_add(other.total);
+    }
+
+    fn aggregate(&self, series: &Series) -> Aggregate {
+        let count = self.count.max(1) as i64;
+        let half = if self.sum < 0 { -count / 2 } else { count / 2 };
+        Aggregate {
+            start: self.start,
+            sensor: series.sensor,
+            quantity: series.quantity,
+            precision: series.precision,
+            count: self.count.min(u16::MAX as u32) as u16,
+            min: self.min,
+            max: self.max,
+            mean: ((self.sum + half) / count) as i32,
+            total: self.total,
+        }
+    }
+}
+
+struct Series {
+    /// Hash of the sensor's name, as logged
+    sensor: u16,
+    quantity: Quantity,
+    precision: u8,
+    /// Last value of an accumulating quantity, to tell the increase by
+    last: Option<i32>,
+    hour: Accumulator,
+    day: Accumulator,
+}
+
+#[derive(Default)]
+pub struct Rollup {
+    series: Vec<Series, MAX_SERIES>,
+}
+
+impl Rollup {
+    /// The series of a measurement, started at `now` if new
+    fn series(
+        &mut self,
+        now: Timestamp,
+        sensor: u16,
+        quantity: Quantity,
+        precision: u8,
+    ) -> Option<&mut Series> {
+        let i = match self
+            .series
+            .iter()
+            .position(|s| s.sensor == sensor && s.quantity == quantity)
+        {
+            Some(i) => i,
+            None => {
+                let new = Series {
+                    sensor,
+                    quantity,
+                    precision,
+                    last: None,
+                    hour: Accumulator::new(start_of(now, HOUR)),
+                    day: Accumulator::new(start_of(now, DAY)),
+                };
+                // More series than kept are not aggregated
+                self.series.push(new).ok()?;
+                self.series.len() - 1
+            }
+        };
+        Some(&mut self.series[i])
+    }
+
+    /// Writes out the periods of all series that are over at `now` with
+    /// `emit`, and starts the ones containing it
+    pub fn roll<E: FnMut(Tier, &Aggregate)>(&mut self, now: Timestamp, mut emit: E) {
+        let hour = start_of(now, HOUR);
+        let day = start_of(now, DAY);
+        for series in &mut self.series {
+            if series.hour.start != hour {
+                if series.hour.count > 0 {
+                    emit(Tier::Hourly, &series.hour.aggregate(series));
+                    series.day.merge(&series.hour);
+                }
+                series.hour = Accumulator::new(hour);
+            }
+            if series.day.start != day {
+                if series.day.count > 0 {
+                    emit(Tier::Daily, &series.day.aggregate(series));
+                }
+                series.day = Accumulator::new(day);
+            }
+        }
+    }
+
+    /// Adds a logged measurement to its series, writing out the periods it
+    /// ends with `emit`
+    pub fn add<E: FnMut(Tier, &Aggregate)>(&mut self, entry: &Entry, emit: E) {
+        self.roll(entry.timestamp, emit);
+
+        let Some(series) = self.series(
+            entry.timestamp,
+            entry.sensor,
+            entry.quantity,
+            entry.precision,
+        ) else {
+            return;
+        };
+        let increase = series.increase(entry.value);
+        series.hour.add(entry.value, increase);
+    }
+
+    /// Takes note of a measurement aggregated before a reboot
+    pub fn skip(&mut self, entry: &Entry) {
+        let series = self.series(
+            entry.timestamp,
+            entry.sensor,
+            entry.quantity,
+            entry.precision,
+        );
+        if let Some(series) = series {
+            series.increase(entry.value);
+        }
+    }
+
+    /// Adds an hourly aggregate written before a reboot to its day
+    pub fn add_hour<E: FnMut(Tier, &Aggregate)>(&mut self, aggregate: &Aggregate, emit: E) {
+        self.roll(aggregate.start, emit);
+
+        let Some(series) = self.series(
+            aggregate.start,
+            aggregate.sensor,
+            aggregate.quantity,
+            aggregate.precision,
+        ) else {
+            return;
+        };
+        series.day.merge(&Accumulator::from_aggregate(aggregate));
+    }
+}
+
+impl Series {
+    /// How much an accumulating quantity grew since its last value. The
+    /// rain gauge's count restarts at midnight.
+    fn increase(&mut self, value: i32) -> i32 {
+        if self.quantity != Quantity::Precipitation {
+            return 0;
+        }
+        let increase = match self.last {
+            Some(last) if value >= last => value - last,
+            Some(_) => value,
+            None => 0,
+        };
+        self.last = Some(value);
+        increase
+    }
+}
@@ -2,7 +2,9 @@ mod request;
 mod response;
 mod router;
 mod server;
+mod stream;
 
 pub use request::{Method, KeyValueMap, GetAs, GetStr};
-pub use response::{HttpResponse, StatusCode};
+pub use response::{ContentType, HttpResponse, StatusCode};
 pub use server::HttpServer;
+pub use stream::BodyStream;
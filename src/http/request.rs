@@ -1,7 +1,7 @@
 use core::str::FromStr;
 
 use defmt::Format;
-use heapless::{LinearMap, String};
+use heapless::{LinearMap, String, Vec};
 
 use super::response::StatusCode;
 
@@ -38,7 +38,8 @@ impl core::convert::TryFrom<&str> for Method {
     }
 }
 
-pub type KeyValueMap = LinearMap<String<16>, String<16>, 8>;
+/// Decoded query or form fields, values long enough for WiFi passphrases
+pub type KeyValueMap = LinearMap<String<16>, String<64>, 16>;
 
 pub trait GetAs<T> {
     fn get_as(&self, key: &str) -> Result<T, ()>;
@@ -82,6 +83,8 @@ impl HttpRequest {
         let (method, path, parameters) = Self::parse_header(header_str)?;
         let payload = if payload_str.is_empty() {
             None
+        } else if Self::is_json(header_str) {
+            Some(Self::parse_json(payload_str)?)
         } else {
             Some(Self::parse_key_value(payload_str)?)
         };
@@ -125,21 +128,201 @@ impl HttpRequest {
             let (key, value) = pair
                 .split_once("=")
                 .ok_or(StatusCode::UnprocessableContent)?;
-            map.insert(
-                String::from_str(key).map_err(|_| StatusCode::UriTooLong)?,
-                String::from_str(value).map_err(|_| StatusCode::UriTooLong)?,
-            )
-            .map_err(|_| StatusCode::UriTooLong)?;
+            map.insert(Self::decode(key)?, Self::decode(value)?)
+                .map_err(|_| StatusCode::UriTooLong)?;
         }
 
         Ok(map)
     }
 
+    /// Whether the payload is sent as JSON rather than as form fields
+    fn is_json(header_str: &str) -> bool {
+        header_str.split("\r\n").skip(1).any(|line| {
+            line.split_once(':').is_some_and(|(name, value)| {
+                name.eq_ignore_ascii_case("content-type")
+                    && value.trim_start().starts_with("application/json")
+            })
+        })
+    }
+
+    /// Reads a JSON object of strings, numbers and booleans into fields as a
+    /// form would have them, numbers and booleans as written and `null` as
+    /// if left out
+    fn parse_json(text: &str) -> Result<KeyValueMap, StatusCode> {
+        let mut map: KeyValueMap = LinearMap::new();
+        let mut json = JsonReader {
+            bytes: text.trim().as_bytes(),
+            position: 0,
+        };
+
+        json.expect(b'{')?;
+        if json.peek() == Some(b'}') {
+            json.position += 1;
+        } else {
+            loop {
+                let key = json.string()?;
+                json.expect(b':')?;
+                let value = match json.peek() {
+                    Some(b'"') => Some(json.string()?),
+                    _ => match json.scalar()? {
+                        "null" => None,
+                        scalar => {
+                            Some(String::from_str(scalar).map_err(|_| StatusCode::UriTooLong)?)
+                        }
+                    },
+                };
+                if let Some(value) = value {
+                    map.insert(key, value).map_err(|_| StatusCode::UriTooLong)?;
+                }
+
+                match json.next() {
+                    Some(b',') => continue,
+                    Some(b'}') => break,
+                    _ => return Err(StatusCode::BadRequest),
+                }
+            }
+        }
+
+        if json.peek().is_some() {
+            return Err(StatusCode::BadRequest);
+        }
+        Ok(map)
+    }
+
+    /// Undoes the `+` for space and `%XX` escapes of URL encoding
+    fn decode<const N: usize>(text: &str) -> Result<String<N>, StatusCode> {
+        let mut decoded: Vec<u8, N> = Vec::new();
+        let mut bytes = text.bytes();
+        while let Some(byte) = bytes.next() {
+            let byte = match byte {
+                b'+' => b' ',
+                b'%' => {
+                    let mut digit = || {
+                        bytes
+                            .next()
+                            .and_then(|b| (b as char).to_digit(16))
+                            .ok_or(StatusCode::BadRequest)
+                    };
+                    (digit()? * 16 + digit()?) as u8
+                }
+                byte => byte,
+            };
+            decoded.push(byte).map_err(|_| StatusCode::UriTooLong)?;
+        }
+
+        String::from_utf8(decoded).map_err(|_| StatusCode::BadRequest)
+    }
+
     pub fn get_identification(&self) -> RequestIndentification<'_> {
         (self.path.as_str(), self.method)
     }
 }
 
+/// Reads the tokens of a JSON text, skipping the whitespace between them
+struct JsonReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> JsonReader<'a> {
+    fn peek(&mut self) -> Option<u8> {
+        while self
+            .bytes
+            .get(self.position)
+            .is_some_and(|b| b.is_ascii_whitespace())
+        {
+            self.position += 1;
+        }
+        self.bytes.get(self.position).copied()
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let byte = self.peek()?;
+        self.position += 1;
+        Some(byte)
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), StatusCode> {
+        match self.next() {
+            Some(b) if b == byte => Ok(()),
+            _ => Err(StatusCode::BadRequest),
+        }
+    }
+
+    /// A number, `true`, `false` or `null`, as written
+    fn scalar(&mut self) -> Result<&'a str, StatusCode> {
+        self.peek();
+        let start = self.position;
+        while self
+            .bytes
+            .get(self.position)
+            .is_some_and(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'+' | b'.'))
+        {
+            self.position += 1;
+        }
+
+        let scalar = core::str::from_utf8(&self.bytes[start..self.position])
+            .map_err(|_| StatusCode::BadRequest)?;
+        let valid = matches!(scalar, "true" | "false" | "null")
+            || scalar.parse::<f64>().is_ok_and(|n| n.is_finite());
+        if valid {
+            Ok(scalar)
+        } else {
+            Err(StatusCode::BadRequest)
+        }
+    }
+
+    /// A string, unescaped. Escapes of UTF-16 surrogates are not supported.
+    fn string<const N: usize>(&mut self) -> Result<String<N>, StatusCode> {
+        self.expect(b'"')?;
+        let mut string: String<N> = String::new();
+        loop {
+            let rest = self
+                .bytes
+                .get(self.position..)
+                .ok_or(StatusCode::BadRequest)?;
+            let length = rest
+                .iter()
+                .position(|b| matches!(b, b'"' | b'\\'))
+                .ok_or(StatusCode::BadRequest)?;
+            let text = core::str::from_utf8(&rest[..length]).map_err(|_| StatusCode::BadRequest)?;
+            string.push_str(text).map_err(|_| StatusCode::UriTooLong)?;
+            self.position += length + 1;
+            if rest[length] == b'"' {
+                return Ok(string);
+            }
+
+            let escaped = *self
+                .bytes
+                .get(self.position)
+                .ok_or(StatusCode::BadRequest)?;
+            self.position += 1;
+            let c = match escaped {
+                b'"' => '"',
+                b'\\' => '\\',
+                b'/' => '/',
+                b'b' => '\u{8}',
+                b'f' => '\u{c}',
+                b'n' => '\n',
+                b'r' => '\r',
+                b't' => '\t',
+                b'u' => {
+                    let digits = self
+                        .bytes
+                        .get(self.position..self.position + 4)
+                        .and_then(|digits| core::str::from_utf8(digits).ok())
+                        .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+                        .ok_or(StatusCode::BadRequest)?;
+                    self.position += 4;
+                    char::from_u32(digits).ok_or(StatusCode::BadRequest)?
+                }
+                _ => return Err(StatusCode::BadRequest),
+            };
+            string.push(c).map_err(|_| StatusCode::UriTooLong)?;
+        }
+    }
+}
+
 impl Format for HttpRequest {
     fn format(&self, f: defmt::Formatter) {
         defmt::write!(f, "Method: {}\nPath: {}\n", self.method, self.path);
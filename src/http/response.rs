@@ -7,6 +7,7 @@ use heapless::Vec;
 pub enum StatusCode {
     Ok = 200,
     BadRequest = 400,
+    Forbidden = 403,
     NotFound = 404,
     MethodNotAllowed = 405,
     UriTooLong = 414,
@@ -20,6 +21,7 @@ impl fmt::Display for StatusCode {
         let message = match self {
             StatusCode::Ok => "OK",
             StatusCode::BadRequest => "Bad Request",
+            StatusCode::Forbidden => "Forbidden",
             StatusCode::NotFound => "Not Found",
             StatusCode::MethodNotAllowed => "Method Not Allowed",
             StatusCode::UriTooLong => "URI Too Long",
@@ -34,12 +36,18 @@ impl fmt::Display for StatusCode {
 
 pub enum ContentType {
     TextHtml,
+    TextCsv,
+    ApplicationJson,
+    ApplicationNdjson,
 }
 
 impl fmt::Display for ContentType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let content_type_str = match self {
-            ContentType::TextHtml => "text/html",
+            ContentType::TextHtml => "text/html; charset=utf-8",
+            ContentType::TextCsv => "text/csv; charset=utf-8",
+            ContentType::ApplicationJson => "application/json",
+            ContentType::ApplicationNdjson => "application/x-ndjson",
         };
         f.write_str(content_type_str)
     }
@@ -82,6 +90,11 @@ impl<const CAPACITY: usize> HttpResponse<CAPACITY> {
     pub fn empty(status_code: StatusCode) -> Self {
         Self::new(status_code, Vec::new())
     }
+
+    pub fn with_content_type(mut self, content_type: ContentType) -> Self {
+        self.header.content_type = content_type;
+        self
+    }
 }
 
 impl fmt::Display for HttpResponseHeader {
@@ -98,3 +111,31 @@ impl fmt::Display for HttpResponseHeader {
         Ok(())
     }
 }
+
+/// Header of a response sent with chunked transfer encoding
+pub struct StreamHeader<'a> {
+    pub status_code: StatusCode,
+    pub content_type: ContentType,
+    /// Name to save the body under, instead of displaying it
+    pub attachment: Option<&'a str>,
+}
+
+impl fmt::Display for StreamHeader<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        core::write!(
+            f,
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nTransfer-Encoding: chunked\r\n",
+            self.status_code as u16,
+            self.status_code,
+            self.content_type
+        )?;
+        if let Some(filename) = self.attachment {
+            core::write!(
+                f,
+                "Content-Disposition: attachment; filename=\"{}\"\r\n",
+                filename
+            )?;
+        }
+        f.write_str("\r\n")
+    }
+}
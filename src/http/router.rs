@@ -3,6 +3,7 @@ use heapless::LinearMap;
 use super::{
     request::{HttpRequest, KeyValueMap, Method, RequestIndentification},
     response::HttpResponse,
+    stream::StreamHandler,
     StatusCode,
 };
 
@@ -11,37 +12,79 @@ pub type RequestHandler<const RESPONSE_CAPACITY: usize> = fn(
     content: Option<&KeyValueMap>,
 ) -> HttpResponse<RESPONSE_CAPACITY>;
 
-pub struct Router<'a, const RESPONSE_CAPACITY: usize> {
-    routes: LinearMap<RequestIndentification<'a>, RequestHandler<RESPONSE_CAPACITY>, 32>,
+enum Handler<const RESPONSE_CAPACITY: usize, S> {
+    Buffered(RequestHandler<RESPONSE_CAPACITY>),
+    Stream(StreamHandler<S>),
 }
 
-impl<'a, const RESPONSE_CAPACITY: usize> Router<'a, RESPONSE_CAPACITY> {
+pub enum Response<const RESPONSE_CAPACITY: usize, S> {
+    Buffered(HttpResponse<RESPONSE_CAPACITY>),
+    Stream(S),
+}
+
+pub struct Router<'a, const RESPONSE_CAPACITY: usize, S> {
+    routes: LinearMap<RequestIndentification<'a>, Handler<RESPONSE_CAPACITY, S>, 32>,
+    /// Answers requests no route matches, instead of 404
+    fallback: Option<RequestHandler<RESPONSE_CAPACITY>>,
+}
+
+impl<'a, const RESPONSE_CAPACITY: usize, S> Router<'a, RESPONSE_CAPACITY, S> {
     pub fn empty() -> Self {
         Self {
             routes: LinearMap::new(),
+            fallback: None,
         }
     }
 
-    pub fn route(
+    fn insert(
         self,
         path: &'a str,
         method: Method,
-        handler: RequestHandler<RESPONSE_CAPACITY>,
+        handler: Handler<RESPONSE_CAPACITY, S>,
     ) -> Result<Self, ()> {
         let mut routes = self.routes;
         routes.insert((path, method), handler).map_err(|_| ())?;
-        Ok(Self { routes })
+        Ok(Self { routes, ..self })
+    }
+
+    pub fn route(
+        self,
+        path: &'a str,
+        method: Method,
+        handler: RequestHandler<RESPONSE_CAPACITY>,
+    ) -> Result<Self, ()> {
+        self.insert(path, method, Handler::Buffered(handler))
+    }
+
+    pub fn stream(self, path: &'a str, handler: StreamHandler<S>) -> Result<Self, ()> {
+        self.insert(path, Method::GET, Handler::Stream(handler))
+    }
+
+    pub fn fallback(self, handler: RequestHandler<RESPONSE_CAPACITY>) -> Self {
+        Self {
+            fallback: Some(handler),
+            ..self
+        }
     }
 
-    pub fn handle(&self, http_request: HttpRequest) -> HttpResponse<RESPONSE_CAPACITY> {
+    pub fn handle(&self, http_request: HttpRequest) -> Response<RESPONSE_CAPACITY, S> {
         let key = http_request.get_identification();
-        let handler = self.routes.get(&key);
-        match handler {
-            Some(handler) => handler(
+        match self.routes.get(&key) {
+            Some(Handler::Buffered(handler)) => Response::Buffered(handler(
                 http_request.parameters.as_ref(),
                 http_request.payload.as_ref(),
-            ),
-            None => HttpResponse::empty(StatusCode::NotFound),
+            )),
+            Some(Handler::Stream(handler)) => match handler(http_request.parameters.as_ref()) {
+                Ok(stream) => Response::Stream(stream),
+                Err(status_code) => Response::Buffered(HttpResponse::empty(status_code)),
+            },
+            None => Response::Buffered(match self.fallback {
+                Some(handler) => handler(
+                    http_request.parameters.as_ref(),
+                    http_request.payload.as_ref(),
+                ),
+                None => HttpResponse::empty(StatusCode::NotFound),
+            }),
         }
     }
 }
@@ -1,9 +1,9 @@
 use core::{fmt::Write as _, str};
 
 use super::request::{HttpRequest, Method};
-use super::response::{HttpResponse, StatusCode};
-use super::router::{RequestHandler, Router};
-use cyw43::Control;
+use super::response::{HttpResponse, StatusCode, StreamHeader};
+use super::router::{RequestHandler, Response, Router};
+use super::stream::{BodyStream, StreamHandler};
 use defmt::*;
 use embassy_net::{
     tcp::{Error, TcpSocket},
@@ -15,19 +15,18 @@ use heapless::Vec;
 
 const PORT: u16 = 80;
 
-pub struct HttpServer<'a, const BUF_SIZE: usize, const RESPONSE_CAPACITY: usize> {
+pub struct HttpServer<'a, const BUF_SIZE: usize, const RESPONSE_CAPACITY: usize, S> {
     rx_buffer: [u8; BUF_SIZE],
     tx_buffer: [u8; BUF_SIZE],
     buffer: Vec<u8, BUF_SIZE>,
     stack: Stack<'a>,
-    control: Control<'a>,
-    router: Router<'a, RESPONSE_CAPACITY>,
+    router: Router<'a, RESPONSE_CAPACITY, S>,
 }
 
-impl<'a, 'b, const BUF_SIZE: usize, const RESPONSE_CAPACITY: usize>
-    HttpServer<'a, BUF_SIZE, RESPONSE_CAPACITY>
+impl<'a, 'b, const BUF_SIZE: usize, const RESPONSE_CAPACITY: usize, S: BodyStream>
+    HttpServer<'a, BUF_SIZE, RESPONSE_CAPACITY, S>
 {
-    pub fn new(stack: Stack<'a>, control: Control<'a>) -> Self {
+    pub fn new(stack: Stack<'a>) -> Self {
         let rx_buffer = [0; BUF_SIZE];
         let tx_buffer = [0; BUF_SIZE];
         let buffer = Vec::<u8, BUF_SIZE>::new();
@@ -37,14 +36,12 @@ impl<'a, 'b, const BUF_SIZE: usize, const RESPONSE_CAPACITY: usize>
             tx_buffer,
             buffer,
             stack,
-            control,
             router: Router::empty(),
         }
     }
 
     async fn init_connection(
         stack: Stack<'b>,
-        control: &mut Control<'_>,
         rx_buffer: &'b mut [u8],
         tx_buffer: &'b mut [u8],
     ) -> Option<TcpSocket<'b>> {
@@ -52,7 +49,6 @@ impl<'a, 'b, const BUF_SIZE: usize, const RESPONSE_CAPACITY: usize>
         socket.set_timeout(Some(Duration::from_secs(5)));
 
         info!("Listening on TCP port {}", PORT);
-        control.gpio_set(0, true).await;
 
         if let Err(e) = socket.accept(PORT).await {
             warn!("Accept error: {:?}", e);
@@ -84,13 +80,17 @@ impl<'a, 'b, const BUF_SIZE: usize, const RESPONSE_CAPACITY: usize>
         };
         buffer.truncate(n);
 
-        let request_str = str::from_utf8(buffer.as_slice()).unwrap();
-        debug!("Received:\n{}", request_str);
-        let http_request = HttpRequest::parse(request_str).unwrap();
+        // Malformed requests are answered with an error, not a panic
+        let http_request = str::from_utf8(buffer.as_slice())
+            .map_err(|_| StatusCode::BadRequest)
+            .and_then(|request_str| {
+                debug!("Received:\n{}", request_str);
+                HttpRequest::parse(request_str)
+            });
 
         buffer.clear();
 
-        Ok(http_request)
+        http_request
     }
 
     async fn send_response(
@@ -104,6 +104,43 @@ impl<'a, 'b, const BUF_SIZE: usize, const RESPONSE_CAPACITY: usize>
         socket.write_all(&response.content).await
     }
 
+    /// Sends a streamed body with chunked transfer encoding, one chunk per
+    /// fill of `buffer`
+    async fn send_stream(
+        socket: &mut TcpSocket<'_>,
+        buffer: &mut Vec<u8, BUF_SIZE>,
+        mut stream: S,
+    ) -> Result<(), Error> {
+        let mut header_buffer: Vec<u8, 256> = Vec::new();
+        let header = StreamHeader {
+            status_code: StatusCode::Ok,
+            content_type: stream.content_type(),
+            attachment: stream.attachment(),
+        };
+        core::write!(header_buffer, "{}", header).unwrap();
+        socket.write_all(header_buffer.as_slice()).await?;
+
+        loop {
+            buffer.clear();
+            let more = stream.fill(buffer);
+
+            if !buffer.is_empty() {
+                header_buffer.clear();
+                core::write!(header_buffer, "{:x}\r\n", buffer.len()).unwrap();
+                socket.write_all(header_buffer.as_slice()).await?;
+                socket.write_all(buffer.as_slice()).await?;
+                socket.write_all(b"\r\n").await?;
+            }
+
+            if !more {
+                break;
+            }
+        }
+        buffer.clear();
+
+        socket.write_all(b"0\r\n\r\n").await
+    }
+
     pub fn route(
         mut self,
         path: &'a str,
@@ -117,26 +154,43 @@ impl<'a, 'b, const BUF_SIZE: usize, const RESPONSE_CAPACITY: usize>
         self
     }
 
+    pub fn stream(mut self, path: &'a str, handler: StreamHandler<S>) -> Self {
+        self.router = self
+            .router
+            .stream(path, handler)
+            .expect("Couldn't insert stream handler - router full");
+        self
+    }
+
+    pub fn fallback(mut self, handler: RequestHandler<RESPONSE_CAPACITY>) -> Self {
+        self.router = self.router.fallback(handler);
+        self
+    }
+
     pub async fn run(mut self) {
         loop {
-            let mut socket = Self::init_connection(
-                self.stack,
-                &mut self.control,
-                &mut self.rx_buffer,
-                &mut self.tx_buffer,
-            )
-            .await
-            .unwrap();
+            let mut socket =
+                Self::init_connection(self.stack, &mut self.rx_buffer, &mut self.tx_buffer)
+                    .await
+                    .unwrap();
 
             loop {
                 let request = Self::get_request(&mut socket, &mut self.buffer).await;
 
                 let response = match request {
                     Ok(http_request) => self.router.handle(http_request),
-                    Err(e) => HttpResponse::empty(e),
+                    Err(e) => Response::Buffered(HttpResponse::empty(e)),
                 };
 
-                if Self::send_response(&mut socket, response).await.is_err() {
+                let sent = match response {
+                    Response::Buffered(response) => {
+                        Self::send_response(&mut socket, response).await
+                    }
+                    Response::Stream(stream) => {
+                        Self::send_stream(&mut socket, &mut self.buffer, stream).await
+                    }
+                };
+                if sent.is_err() {
                     break;
                 }
             }
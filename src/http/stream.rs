@@ -0,0 +1,19 @@
+use heapless::Vec;
+
+use super::{request::KeyValueMap, response::ContentType, StatusCode};
+
+/// A response body too large to buffer, produced a chunk at a time
+pub trait BodyStream {
+    fn content_type(&self) -> ContentType;
+
+    /// Name for browsers to download the body as, if it's not for display
+    fn attachment(&self) -> Option<&'static str> {
+        None
+    }
+
+    /// Appends the next part of the body to `chunk`, returning false once
+    /// the body is complete
+    fn fill<const N: usize>(&mut self, chunk: &mut Vec<u8, N>) -> bool;
+}
+
+pub type StreamHandler<S> = fn(parameters: Option<&KeyValueMap>) -> Result<S, StatusCode>;
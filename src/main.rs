@@ -2,31 +2,48 @@
 #![no_main]
 
 use core::net::Ipv4Addr;
-use cyw43::{Control, JoinOptions};
 use cyw43_pio::PioSpi;
 use defmt::*;
 use embassy_executor::Spawner;
 use embassy_net::{Ipv4Cidr, Stack, StackResources};
+use embassy_rp::adc;
 use embassy_rp::bind_interrupts;
 use embassy_rp::clocks::RoscRng;
-use embassy_rp::gpio::{Level, Output, Pin};
-use embassy_rp::peripherals::{DMA_CH0, PIO0};
+use embassy_rp::gpio::{AnyPin, Input, Level, Output, Pin, Pull};
+use embassy_rp::i2c::{self, I2c};
+use embassy_rp::peripherals::{DMA_CH0, I2C0, PIO0};
 use embassy_rp::pio::{InterruptHandler, Pio};
-use handlers::{write_temperature, write_time, INDEX};
+use embassy_time::{Duration, Instant, Timer};
+use handlers::{
+    write_altitude, write_calibration, write_config, write_config_json, write_config_login,
+    write_diagnostics, write_history_status, write_network_status, write_rain,
+    write_sample_interval, write_setup, write_temperature, write_time, write_wifi_scan, write_wind,
+    INDEX,
+};
 use heapless::Vec;
-use http::{HttpResponse, HttpServer, Method, StatusCode};
+use http::{ContentType, HttpResponse, HttpServer, Method, StatusCode};
 use rand_core::RngCore;
 use static_cell::StaticCell;
 use {defmt_rtt as _, panic_probe as _};
 
+mod calibration;
+mod config;
+mod crc;
 mod devices;
 mod handlers;
+mod history;
 mod http;
+mod meteo;
+mod network;
+mod pressure;
+mod provisioning;
+mod statistics;
 
 include!("secrets.rs");
 
 bind_interrupts!(struct Irqs {
     PIO0_IRQ_0 => InterruptHandler<PIO0>;
+    I2C0_IRQ => i2c::InterruptHandler<I2C0>;
 });
 
 #[embassy_executor::task]
@@ -42,8 +59,85 @@ async fn net_task(mut runner: embassy_net::Runner<'static, cyw43::NetDriver<'sta
 }
 
 #[embassy_executor::task]
-async fn http_server(stack: Stack<'static>, control: Control<'static>) {
-    let http_server: HttpServer<'_, 4096, 4096> = HttpServer::new(stack, control)
+async fn rain_gauge(pin: AnyPin) -> ! {
+    devices::rain::run(pin).await
+}
+
+#[embassy_executor::task]
+async fn anemometer(pin: AnyPin) -> ! {
+    devices::wind::count_pulses(pin).await
+}
+
+#[embassy_executor::task]
+async fn wind_vane(vane: adc::Channel<'static>) -> ! {
+    devices::wind::sample(vane).await
+}
+
+#[embassy_executor::task]
+async fn rebooter() -> ! {
+    devices::system::reboot_when_scheduled().await
+}
+
+#[embassy_executor::task]
+async fn link_supervisor(
+    control: cyw43::Control<'static>,
+    stack: Stack<'static>,
+    network: config::Network,
+) -> ! {
+    network::supervise(control, stack, network).await
+}
+
+#[embassy_executor::task]
+async fn address_watcher(stack: Stack<'static>) -> ! {
+    network::watch(stack).await
+}
+
+#[embassy_executor::task]
+async fn dhcp_server(stack: Stack<'static>) -> ! {
+    provisioning::dhcp::run(stack).await
+}
+
+#[embassy_executor::task]
+async fn dns_server(stack: Stack<'static>) -> ! {
+    provisioning::dns::run(stack).await
+}
+
+#[embassy_executor::task]
+async fn sampler() -> ! {
+    let mut last_logged: Option<Instant> = None;
+
+    loop {
+        devices::sensor::sample_all().await;
+
+        let log_interval = Duration::from_secs(config::get().await.log_interval as u64);
+        let log = !matches!(last_logged, Some(t) if t.elapsed() < log_interval);
+        if log {
+            last_logged = Some(Instant::now());
+        }
+        devices::sensor::latest(|reading| {
+            if let Ok(measurements) = &reading.result {
+                for measurement in measurements {
+                    statistics::record(reading.sensor, measurement);
+                    if log {
+                        history::record(reading.sensor, measurement);
+                    }
+                }
+            }
+        });
+
+        // Station pressure is reported in hPa with two decimals, i.e. in Pa
+        let pressure = devices::sensor::latest_measurement(devices::sensor::Quantity::Pressure);
+        if let Some((timestamp, pressure)) = pressure.and_then(|m| Some((m.timestamp?, m.value))) {
+            pressure::record(timestamp, pressure).await;
+        }
+
+        Timer::after_secs(config::get().await.sample_interval as u64).await;
+    }
+}
+
+#[embassy_executor::task]
+async fn http_server(stack: Stack<'static>) {
+    let http_server: HttpServer<'_, 4096, 4096, handlers::Stream> = HttpServer::new(stack)
         .route("/", Method::GET, |_, _| {
             HttpResponse::from_slice(StatusCode::Ok, INDEX.as_bytes())
                 .unwrap_or(HttpResponse::empty(StatusCode::InternalServerError))
@@ -58,12 +152,159 @@ async fn http_server(stack: Stack<'static>, control: Control<'static>) {
             write_temperature(&mut response_buffer);
             HttpResponse::new(StatusCode::Ok, response_buffer)
         })
+        .route("/altitude", Method::GET, |_, _| {
+            let mut response_buffer: Vec<u8, 4096> = Vec::new();
+            write_altitude(&mut response_buffer);
+            HttpResponse::new(StatusCode::Ok, response_buffer)
+        })
+        .route("/altitude", Method::POST, |_, content| {
+            let status_code = match handlers::set_altitude(content) {
+                Ok(_) => StatusCode::Ok,
+                Err(c) => c,
+            };
+            HttpResponse::from_slice(status_code, "<a href='/'>back</a>".as_bytes()).unwrap()
+        })
+        .route("/rain", Method::GET, |_, _| {
+            let mut response_buffer: Vec<u8, 4096> = Vec::new();
+            write_rain(&mut response_buffer);
+            HttpResponse::new(StatusCode::Ok, response_buffer)
+        })
+        .route("/rain", Method::POST, |_, content| {
+            let status_code = match handlers::set_rain_calibration(content) {
+                Ok(_) => StatusCode::Ok,
+                Err(c) => c,
+            };
+            HttpResponse::from_slice(status_code, "<a href='/'>back</a>".as_bytes()).unwrap()
+        })
+        .route("/wind", Method::GET, |_, _| {
+            let mut response_buffer: Vec<u8, 4096> = Vec::new();
+            write_wind(&mut response_buffer);
+            HttpResponse::new(StatusCode::Ok, response_buffer)
+        })
+        .route("/wind", Method::POST, |_, content| {
+            let status_code = match handlers::set_wind_calibration(content) {
+                Ok(_) => StatusCode::Ok,
+                Err(c) => c,
+            };
+            HttpResponse::from_slice(status_code, "<a href='/'>back</a>".as_bytes()).unwrap()
+        })
+        .route("/sampling", Method::GET, |_, _| {
+            let mut response_buffer: Vec<u8, 4096> = Vec::new();
+            write_sample_interval(&mut response_buffer);
+            HttpResponse::new(StatusCode::Ok, response_buffer)
+        })
+        .route("/sampling", Method::POST, |_, content| {
+            let status_code = match handlers::set_sample_interval(content) {
+                Ok(_) => StatusCode::Ok,
+                Err(c) => c,
+            };
+            HttpResponse::from_slice(status_code, "<a href='/'>back</a>".as_bytes()).unwrap()
+        })
+        .route("/calibration", Method::GET, |_, _| {
+            let mut response_buffer: Vec<u8, 4096> = Vec::new();
+            write_calibration(&mut response_buffer);
+            HttpResponse::new(StatusCode::Ok, response_buffer)
+        })
+        .route("/calibration", Method::POST, |_, content| {
+            let status_code = match handlers::set_calibration(content) {
+                Ok(_) => StatusCode::Ok,
+                Err(c) => c,
+            };
+            HttpResponse::from_slice(status_code, "<a href='/'>back</a>".as_bytes()).unwrap()
+        })
+        .stream("/statistics", handlers::statistics)
+        .stream("/api/v1/history", handlers::history)
+        .route("/api/v1/history/status", Method::GET, |_, _| {
+            let mut response_buffer: Vec<u8, 4096> = Vec::new();
+            write_history_status(&mut response_buffer);
+            HttpResponse::new(StatusCode::Ok, response_buffer)
+                .with_content_type(ContentType::ApplicationJson)
+        })
+        .stream("/export.csv", handlers::export_csv)
+        .stream("/export.ndjson", handlers::export_ndjson)
+        .route("/config", Method::GET, |_, _| {
+            let mut response_buffer: Vec<u8, 4096> = Vec::new();
+            write_config_login(&mut response_buffer);
+            HttpResponse::new(StatusCode::Ok, response_buffer)
+        })
+        .route("/config/view", Method::POST, |_, content| {
+            let mut response_buffer: Vec<u8, 4096> = Vec::new();
+            let status_code = write_config(content, &mut response_buffer);
+            HttpResponse::new(status_code, response_buffer)
+        })
+        .route("/config", Method::POST, |_, content| {
+            let mut response_buffer: Vec<u8, 4096> = Vec::new();
+            let status_code = handlers::set_config(content, &mut response_buffer);
+            HttpResponse::new(status_code, response_buffer)
+        })
+        .route("/config/reboot", Method::POST, |_, content| {
+            let (status_code, message) = match handlers::reboot(content) {
+                Ok(_) => (
+                    StatusCode::Ok,
+                    "Rebooting, <a href='/'>back</a> in a moment",
+                ),
+                Err(c) => (c, "<a href='/config'>back</a>"),
+            };
+            HttpResponse::from_slice(status_code, message.as_bytes()).unwrap()
+        })
+        .route("/api/v1/config", Method::GET, |parameters, _| {
+            let mut response_buffer: Vec<u8, 4096> = Vec::new();
+            match write_config_json(parameters, &mut response_buffer) {
+                Ok(_) => HttpResponse::new(StatusCode::Ok, response_buffer)
+                    .with_content_type(ContentType::ApplicationJson),
+                Err(c) => HttpResponse::empty(c),
+            }
+        })
+        .route("/api/v1/config", Method::POST, |_, content| {
+            let mut response_buffer: Vec<u8, 4096> = Vec::new();
+            match handlers::set_config_json(content, &mut response_buffer) {
+                Ok(_) => HttpResponse::new(StatusCode::Ok, response_buffer)
+                    .with_content_type(ContentType::ApplicationJson),
+                Err(c) => HttpResponse::empty(c),
+            }
+        })
+        .route("/setup", Method::GET, |_, _| {
+            let mut response_buffer: Vec<u8, 4096> = Vec::new();
+            write_setup(&mut response_buffer);
+            HttpResponse::new(StatusCode::Ok, response_buffer)
+        })
+        .route("/setup", Method::POST, |_, content| {
+            let mut response_buffer: Vec<u8, 4096> = Vec::new();
+            let status_code = handlers::set_setup(content, &mut response_buffer);
+            HttpResponse::new(status_code, response_buffer)
+        })
+        .route("/api/v1/network", Method::GET, |_, _| {
+            let mut response_buffer: Vec<u8, 4096> = Vec::new();
+            write_network_status(&mut response_buffer);
+            HttpResponse::new(StatusCode::Ok, response_buffer)
+                .with_content_type(ContentType::ApplicationJson)
+        })
+        .route("/api/v1/wifi/scan", Method::GET, |_, _| {
+            let mut response_buffer: Vec<u8, 4096> = Vec::new();
+            write_wifi_scan(&mut response_buffer);
+            HttpResponse::new(StatusCode::Ok, response_buffer)
+                .with_content_type(ContentType::ApplicationJson)
+        })
+        .route("/diagnostics", Method::GET, |_, _| {
+            let mut response_buffer: Vec<u8, 4096> = Vec::new();
+            write_diagnostics(&mut response_buffer);
+            HttpResponse::new(StatusCode::Ok, response_buffer)
+        })
         .route("/rtc", Method::POST, |_, content| {
             let status_code = match handlers::set_time(content) {
                 Ok(_) => StatusCode::Ok,
                 Err(c) => c,
             };
             HttpResponse::from_slice(status_code, "<a href='/'>back</a>".as_bytes()).unwrap()
+        })
+        // Whatever phones ask for to detect a captive portal gets the setup page
+        .fallback(|_, _| {
+            if !provisioning::active() {
+                return HttpResponse::empty(StatusCode::NotFound);
+            }
+            let mut response_buffer: Vec<u8, 4096> = Vec::new();
+            write_setup(&mut response_buffer);
+            HttpResponse::new(StatusCode::Ok, response_buffer)
         });
     http_server.run().await;
 }
@@ -75,6 +316,9 @@ async fn main(spawner: Spawner) {
     let p = embassy_rp::init(Default::default());
     let mut rng = RoscRng;
 
+    let setup_button = Input::new(p.PIN_22, Pull::Up);
+    let setup_requested = provisioning::button_held(&setup_button).await;
+
     let fw = include_bytes!("../cyw43-firmware/43439A0.bin");
     let clm = include_bytes!("../cyw43-firmware/43439A0_clm.bin");
 
@@ -87,7 +331,34 @@ async fn main(spawner: Spawner) {
 
     // Init readout devices
     devices::rtc::init(p.RTC).await;
-    devices::dht::init(p.PIN_27.degrade()).await;
+    devices::flash::init(p.FLASH);
+    config::init().await;
+    calibration::init();
+    history::init();
+    // Set to `Model::Dht11` for boards fitted with the blue DHT11
+    const DHT_MODEL: devices::dht::Model = devices::dht::Model::Dht22;
+    devices::dht::init(p.PIN_27.degrade(), DHT_MODEL).await;
+    let i2c = I2c::new_async(p.I2C0, p.PIN_5, p.PIN_4, Irqs, i2c::Config::default());
+    let i2c_device = devices::i2c::init(i2c);
+    devices::bme280::init(i2c_device(), Default::default()).await;
+    devices::sht::init(
+        i2c_device(),
+        devices::sht::Model::Sht4x,
+        devices::sht::Mode::SingleShot,
+    )
+    .await;
+    devices::bh1750::init(i2c_device()).await;
+    devices::veml6075::init(i2c_device()).await;
+    devices::rain::init().await;
+    spawner.spawn(rain_gauge(p.PIN_2.degrade())).unwrap();
+    devices::adc::init(p.ADC);
+    devices::system::init(p.ADC_TEMP_SENSOR);
+    let vane = adc::Channel::new_pin(p.PIN_26, Pull::None);
+    devices::wind::init().await;
+    spawner.spawn(anemometer(p.PIN_3.degrade())).unwrap();
+    spawner.spawn(wind_vane(vane)).unwrap();
+    spawner.spawn(sampler()).unwrap();
+    spawner.spawn(rebooter()).unwrap();
 
     // Init cyw43
     let pwr = Output::new(p.PIN_23, Level::Low);
@@ -114,13 +385,10 @@ async fn main(spawner: Spawner) {
         .await;
 
     // Init network stack
-    let config = embassy_net::Config::ipv4_static(embassy_net::StaticConfigV4 {
-        address: PICO_IP,
-        dns_servers: heapless::Vec::new(),
-        gateway: Some(GATEWAY_IP),
-    });
+    let network = config::get().await.network;
+    let config = network::stack_config(&network);
     let seed = rng.next_u64();
-    static RESOURCES: StaticCell<StackResources<3>> = StaticCell::new();
+    static RESOURCES: StaticCell<StackResources<5>> = StaticCell::new();
     let (stack, runner) = embassy_net::new(
         net_device,
         config,
@@ -129,25 +397,31 @@ async fn main(spawner: Spawner) {
     );
 
     spawner.spawn(net_task(runner)).unwrap();
+    spawner.spawn(address_watcher(stack)).unwrap();
 
-    // Connect to network
-    loop {
-        match control
-            .join(WIFI_NETWORK, JoinOptions::new(WIFI_PASSWORD.as_bytes()))
-            .await
-        {
-            Ok(_) => {
-                info!("Joined network {}", WIFI_NETWORK);
-                break;
-            }
-            Err(err) => {
-                info!(
-                    "Joining {} failed with status = {}",
-                    WIFI_NETWORK, err.status
-                );
-            }
-        }
+    // Connect to network, unless asked for setup
+    if setup_requested {
+        info!("Setup button held");
     }
+    let joined = !setup_requested
+        && network::join(&mut control, &network, provisioning::JOIN_ATTEMPTS).await;
 
-    spawner.spawn(http_server(stack, control)).unwrap();
+    if joined {
+        // Signal a successful join with the on-board LED
+        control.gpio_set(0, true).await;
+        network::acquire(stack, &network).await;
+        spawner
+            .spawn(link_supervisor(control, stack, network))
+            .unwrap();
+    } else {
+        provisioning::start(&mut control, stack).await;
+        spawner.spawn(dhcp_server(stack)).unwrap();
+        spawner.spawn(dns_server(stack)).unwrap();
+    }
+
+    spawner.spawn(http_server(stack)).unwrap();
+
+    if !joined && !setup_requested {
+        provisioning::retry_later().await;
+    }
 }
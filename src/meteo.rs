@@ -0,0 +1,298 @@
+//! Meteorological quantities derived from raw sensor readings
+
+use core::fmt;
+
+use defmt::Format;
+
+/// Standard temperature lapse rate, K/m
+const LAPSE_RATE: f32 = 0.0065;
+/// g * M / (R * L) for dry air
+const BAROMETRIC_EXPONENT: f32 = 5.257;
+
+/// Smallest pressure change (Pa) not considered steady - the 0.1 hPa
+/// resolution of synoptic reports
+const STEADY_THRESHOLD: i32 = 10;
+
+/// Magnus coefficients over water (Sonntag 1990), valid -45..60 °C
+const MAGNUS_WATER: (f32, f32) = (17.62, 243.12);
+/// Magnus coefficients over ice, valid -65..0 °C
+const MAGNUS_ICE: (f32, f32) = (22.46, 272.62);
+
+/// ln(e / e_s(0 °C)) for the vapour pressure e at temperature (°C) and
+/// relative humidity (%) - the "gamma" of the Magnus formula
+fn magnus_gamma(temperature: f32, humidity: f32) -> f32 {
+    let (b, c) = MAGNUS_WATER;
+    libm::logf(humidity / 100.0) + b * temperature / (c + temperature)
+}
+
+/// Dew point (°C) from temperature (°C) and relative humidity (%), using
+/// the Magnus formula
+pub fn dew_point(temperature: f32, humidity: f32) -> f32 {
+    let (b, c) = MAGNUS_WATER;
+    let gamma = magnus_gamma(temperature, humidity);
+    c * gamma / (b - gamma)
+}
+
+/// Frost point (°C): the temperature at which the air would be saturated
+/// with respect to ice. Only meaningful when below 0 °C.
+pub fn frost_point(temperature: f32, humidity: f32) -> f32 {
+    let (b, c) = MAGNUS_ICE;
+    let gamma = magnus_gamma(temperature, humidity);
+    c * gamma / (b - gamma)
+}
+
+/// NOAA heat index (°C) from temperature (°C) and relative humidity (%):
+/// Steadman's simple formula, or the Rothfusz regression with its low and
+/// high humidity adjustments where that exceeds 80 °F
+pub fn heat_index(temperature: f32, humidity: f32) -> f32 {
+    let t = temperature * 1.8 + 32.0;
+    let rh = humidity;
+
+    let simple = 0.5 * (t + 61.0 + (t - 68.0) * 1.2 + rh * 0.094);
+    let index = if (simple + t) / 2.0 < 80.0 {
+        simple
+    } else {
+        let mut index = -42.379 + 2.049_015_2 * t + 10.143_331 * rh
+            - 0.224_755_4 * t * rh
+            - 0.006_837_83 * t * t
+            - 0.054_817_17 * rh * rh
+            + 0.001_228_74 * t * t * rh
+            + 0.000_852_82 * t * rh * rh
+            - 0.000_001_99 * t * t * rh * rh;
+
+        if rh < 13.0 && (80.0..=112.0).contains(&t) {
+            index -= (13.0 - rh) / 4.0 * libm::sqrtf((17.0 - libm::fabsf(t - 95.0)) / 17.0);
+        } else if rh > 85.0 && (80.0..=87.0).contains(&t) {
+            index += (rh - 85.0) / 10.0 * ((87.0 - t) / 5.0);
+        }
+        index
+    };
+
+    (index - 32.0) / 1.8
+}
+
+/// Environment Canada humidex from temperature and dew point (°C)
+pub fn humidex(temperature: f32, dew_point: f32) -> f32 {
+    let vapour_pressure = 6.11 * libm::expf(5417.753 * (1.0 / 273.16 - 1.0 / (273.15 + dew_point)));
+    temperature + 0.5555 * (vapour_pressure - 10.0)
+}
+
+/// Absolute humidity (g/m³) from temperature (°C) and relative humidity (%)
+pub fn absolute_humidity(temperature: f32, humidity: f32) -> f32 {
+    let saturation = 6.112 * libm::expf(17.67 * temperature / (temperature + 243.5));
+    saturation * humidity * 2.1674 / (273.15 + temperature)
+}
+
+/// North American / UK wind chill index (°C) from temperature (°C) and
+/// wind speed (m/s), defined only at or below 10 °C and above 4.8 km/h
+pub fn wind_chill(temperature: f32, wind_speed: f32) -> Option<f32> {
+    let speed = wind_speed * 3.6;
+    if temperature > 10.0 || speed <= 4.8 {
+        return None;
+    }
+
+    let v = libm::powf(speed, 0.16);
+    Some(13.12 + 0.6215 * temperature - 11.37 * v + 0.3965 * temperature * v)
+}
+
+/// Reduces station pressure (hPa) to mean sea level using the barometric
+/// formula, taking the current air temperature (°C) at the station's
+/// altitude (m) as the base of the assumed air column
+pub fn sea_level_pressure(pressure: f32, temperature: f32, altitude: f32) -> f32 {
+    let lapse = LAPSE_RATE * altitude;
+    pressure
+        * libm::powf(
+            1.0 - lapse / (temperature + lapse + 273.15),
+            -BAROMETRIC_EXPONENT,
+        )
+}
+
+#[derive(Format, Clone, Copy, PartialEq, Eq)]
+pub enum Trend {
+    Rising,
+    Steady,
+    Falling,
+}
+
+impl fmt::Display for Trend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let trend = match self {
+            Trend::Rising => "rising",
+            Trend::Steady => "steady",
+            Trend::Falling => "falling",
+        };
+        f.write_str(trend)
+    }
+}
+
+#[derive(Format, Clone, Copy)]
+pub struct PressureTendency {
+    pub trend: Trend,
+    /// Characteristic of pressure tendency, WMO code table 0200
+    pub characteristic: u8,
+    /// Change over the last three hours, in Pa
+    pub change: i32,
+}
+
+/// Classifies the pressure tendency from three samples (Pa) spaced 1.5 h apart
+pub fn pressure_tendency(three_hours_ago: i32, half_way: i32, now: i32) -> PressureTendency {
+    let change = now - three_hours_ago;
+    let first = half_way - three_hours_ago;
+    let second = now - half_way;
+
+    let rising = |d: i32| d >= STEADY_THRESHOLD;
+    let falling = |d: i32| d <= -STEADY_THRESHOLD;
+    let steady = |d: i32| !rising(d) && !falling(d);
+
+    let characteristic = if rising(change) {
+        if falling(second) {
+            0
+        } else if rising(first) && second < first - STEADY_THRESHOLD {
+            1
+        } else if !rising(first) || second > first + STEADY_THRESHOLD {
+            3
+        } else {
+            2
+        }
+    } else if falling(change) {
+        if rising(second) {
+            5
+        } else if falling(first) && second > first + STEADY_THRESHOLD {
+            6
+        } else if !falling(first) || second < first - STEADY_THRESHOLD {
+            8
+        } else {
+            7
+        }
+    } else if rising(first) && falling(second) {
+        0
+    } else if falling(first) && rising(second) {
+        5
+    } else {
+        4
+    };
+
+    let trend = match change {
+        c if rising(c) => Trend::Rising,
+        c if steady(c) => Trend::Steady,
+        _ => Trend::Falling,
+    };
+
+    PressureTendency {
+        trend,
+        characteristic,
+        change,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(value: f32, expected: f32, tolerance: f32, case: impl fmt::Debug) {
+        assert!(
+            (value - expected).abs() <= tolerance,
+            "{} instead of {} for {:?}",
+            value,
+            expected,
+            case
+        );
+    }
+
+    /// Magnus formula dew points as published by dew point calculators, to
+    /// 0.1 °C: temperature, humidity, dew point
+    #[test]
+    fn dew_point_table() {
+        for case @ (t, rh, expected) in [
+            (20.0, 50.0, 9.3),
+            (30.0, 70.0, 23.9),
+            (25.0, 60.0, 16.7),
+            (10.0, 80.0, 6.7),
+            (0.0, 100.0, 0.0),
+        ] {
+            assert_near(dew_point(t, rh), expected, 0.06, case);
+        }
+    }
+
+    /// With the vapour pressure that saturates air over ice, the frost point
+    /// is the temperature itself. WMO saturation vapour pressures in hPa:
+    /// temperature, over water, over ice.
+    #[test]
+    fn frost_point_at_ice_saturation() {
+        for case @ (t, water, ice) in [(-10.0, 2.865, 2.597), (-20.0, 1.254, 1.032)] {
+            assert_near(frost_point(t, 100.0 * ice / water), t, 0.1, case);
+        }
+    }
+
+    /// The NWS heat index chart, in whole °F: temperature (°F), humidity,
+    /// heat index (°F)
+    #[test]
+    fn heat_index_table() {
+        for case @ (t, rh, expected) in [
+            (80.0, 40.0, 80.0),
+            (80.0, 90.0, 86.0),
+            (84.0, 60.0, 88.0),
+            (86.0, 90.0, 105.0),
+            (90.0, 50.0, 95.0),
+            (90.0, 70.0, 106.0),
+            (100.0, 40.0, 109.0),
+            (100.0, 50.0, 118.0),
+        ] {
+            let index = heat_index((t - 32.0) / 1.8, rh) * 1.8 + 32.0;
+            assert_near(index, expected, 0.6, case);
+        }
+    }
+
+    /// Environment Canada's humidex, in whole degrees: temperature, dew
+    /// point, humidex
+    #[test]
+    fn humidex_table() {
+        for case @ (t, dew_point, expected) in [
+            (30.0, 15.0, 34.0),
+            (30.0, 20.0, 38.0),
+            (25.0, 20.0, 33.0),
+            (35.0, 25.0, 47.0),
+        ] {
+            assert_near(humidex(t, dew_point), expected, 0.5, case);
+        }
+    }
+
+    /// Water vapour in saturated air, g/m³
+    #[test]
+    fn absolute_humidity_table() {
+        for case @ (t, expected) in [(0.0, 4.85), (10.0, 9.40), (20.0, 17.3), (30.0, 30.4)] {
+            assert_near(absolute_humidity(t, 100.0), expected, 0.06, case);
+        }
+        assert_near(
+            absolute_humidity(20.0, 50.0),
+            17.3 / 2.0,
+            0.05,
+            (20.0, 50.0),
+        );
+    }
+
+    /// Environment Canada's wind chill table, in whole degrees: temperature,
+    /// wind speed (km/h), wind chill
+    #[test]
+    fn wind_chill_table() {
+        for case @ (t, speed, expected) in [
+            (0.0, 10.0, -3.0),
+            (5.0, 40.0, -1.0),
+            (-10.0, 20.0, -18.0),
+            (-20.0, 30.0, -33.0),
+            (-30.0, 50.0, -49.0),
+            (-40.0, 60.0, -64.0),
+        ] {
+            let Some(wind_chill) = wind_chill(t, speed / 3.6) else {
+                panic!("no wind chill for {:?}", case);
+            };
+            assert_near(wind_chill, expected, 0.5, case);
+        }
+    }
+
+    #[test]
+    fn wind_chill_undefined_when_warm_or_calm() {
+        assert_eq!(wind_chill(15.0, 10.0), None);
+        assert_eq!(wind_chill(-10.0, 1.0), None);
+    }
+}
@@ -0,0 +1,369 @@
+//! Joining the network and staying on it, and the station's address on it,
+//! static or leased by DHCP with the static one as fallback. Of the known
+//! networks, a scan picks the one to join. The address in use, the outages
+//! had and the last scan are kept for the status endpoints.
+
+use core::cell::RefCell;
+
+use cyw43::{Control, JoinOptions, ScanOptions};
+use defmt::{info, warn, Debug2Format, Display2Format};
+use embassy_net::{ConfigV4, Stack, StaticConfigV4};
+use embassy_sync::blocking_mutex::{raw::ThreadModeRawMutex, Mutex};
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+use heapless::{String, Vec};
+
+use crate::{
+    config::{self, IpMode, KnownNetwork, Network},
+    devices, provisioning,
+};
+
+/// How long to wait for a lease before using the static address
+const LEASE_TIMEOUT: Duration = Duration::from_secs(30);
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+/// How often the connection is checked
+const SUPERVISE_INTERVAL: Duration = Duration::from_secs(5);
+/// Waits between attempts to rejoin, doubling from the shortest
+const MIN_BACKOFF: Duration = Duration::from_secs(5);
+const MAX_BACKOFF: Duration = Duration::from_secs(5 * 60);
+/// Access points kept from a scan, the weakest left out
+const MAX_VISIBLE: usize = 16;
+/// The 802.11 capability of networks needing a key
+const CAPABILITY_PRIVACY: u16 = 0x0010;
+
+/// Where the address in use comes from
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Static,
+    Dhcp,
+    /// The static address, for want of a lease
+    Fallback,
+    /// The station's own, in setup mode
+    AccessPoint,
+}
+
+impl Source {
+    pub fn key(&self) -> &'static str {
+        match self {
+            Source::Static => "static",
+            Source::Dhcp => "dhcp",
+            Source::Fallback => "fallback",
+            Source::AccessPoint => "access_point",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Status {
+    /// The network joined last
+    pub ssid: String<32>,
+    pub source: Source,
+    /// Address, gateway and DNS servers, `None` until there is an address
+    pub config: Option<StaticConfigV4>,
+}
+
+/// Connection losses since boot
+#[derive(Clone, Copy)]
+pub struct Outages {
+    pub count: u32,
+    /// Start of the current one, if any
+    pub since: Option<Instant>,
+    pub last: Duration,
+    pub longest: Duration,
+    pub total: Duration,
+}
+
+impl Outages {
+    const NONE: Self = Self {
+        count: 0,
+        since: None,
+        last: Duration::from_ticks(0),
+        longest: Duration::from_ticks(0),
+        total: Duration::from_ticks(0),
+    };
+
+    fn start(&mut self) {
+        self.count += 1;
+        self.since = Some(Instant::now());
+    }
+
+    fn end(&mut self) {
+        if let Some(since) = self.since.take() {
+            self.last = since.elapsed();
+            self.longest = self.longest.max(self.last);
+            self.total += self.last;
+        }
+    }
+}
+
+/// An access point seen in a scan
+#[derive(Clone)]
+pub struct Visible {
+    pub ssid: String<32>,
+    pub bssid: [u8; 6],
+    pub channel: u8,
+    /// In dBm
+    pub rssi: i16,
+    /// WEP or some WPA, the scan results not telling which
+    pub secured: bool,
+}
+
+/// The access points of the last scan, strongest first
+#[derive(Clone)]
+pub struct Scan {
+    pub at: Option<Instant>,
+    pub networks: Vec<Visible, MAX_VISIBLE>,
+}
+
+static SCAN: Mutex<ThreadModeRawMutex, RefCell<Scan>> = Mutex::new(RefCell::new(Scan {
+    at: None,
+    networks: Vec::new(),
+}));
+
+static OUTAGES: Mutex<ThreadModeRawMutex, RefCell<Outages>> =
+    Mutex::new(RefCell::new(Outages::NONE));
+
+static STATUS: Mutex<ThreadModeRawMutex, RefCell<Status>> = Mutex::new(RefCell::new(Status {
+    ssid: String::new(),
+    source: Source::Static,
+    config: None,
+}));
+
+fn static_config(network: &Network) -> StaticConfigV4 {
+    StaticConfigV4 {
+        address: network.address,
+        gateway: network.gateway,
+        dns_servers: heapless::Vec::new(),
+    }
+}
+
+/// Open networks have no passphrase
+fn join_options(password: &str) -> JoinOptions<'_> {
+    if password.is_empty() {
+        JoinOptions::new_open()
+    } else {
+        JoinOptions::new(password.as_bytes())
+    }
+}
+
+/// Looks for access points in reach, kept for the scan endpoint
+async fn scan(control: &mut Control<'_>) {
+    let mut networks: Vec<Visible, MAX_VISIBLE> = Vec::new();
+    let mut scanner = control.scan(ScanOptions::default()).await;
+    while let Some(bss) = scanner.next().await {
+        let (ssid, length) = (bss.ssid, bss.ssid_len as usize);
+        let Some(ssid) = ssid
+            .get(..length)
+            .and_then(|ssid| core::str::from_utf8(ssid).ok())
+            .and_then(|ssid| String::try_from(ssid).ok())
+        else {
+            continue;
+        };
+        let visible = Visible {
+            ssid,
+            bssid: bss.bssid,
+            channel: (bss.chanspec & 0xff) as u8,
+            rssi: bss.rssi,
+            secured: bss.capability & CAPABILITY_PRIVACY != 0,
+        };
+
+        // Access points may be reported more than once
+        if let Some(seen) = networks.iter_mut().find(|v| v.bssid == visible.bssid) {
+            *seen = visible;
+        } else if let Err(visible) = networks.push(visible) {
+            let weakest = networks.iter_mut().min_by_key(|v| v.rssi);
+            if let Some(weakest) = weakest.filter(|w| w.rssi < visible.rssi) {
+                *weakest = visible;
+            }
+        }
+    }
+    networks.sort_unstable_by_key(|v| core::cmp::Reverse(v.rssi));
+
+    info!("Scan found {} access points", networks.len());
+    SCAN.lock(|scan| {
+        *scan.borrow_mut() = Scan {
+            at: Some(Instant::now()),
+            networks,
+        }
+    });
+}
+
+pub fn last_scan() -> Scan {
+    SCAN.lock(|scan| scan.borrow().clone())
+}
+
+/// The known network to join: of those in the last scan, the one of the
+/// highest priority, and the strongest of equal ones. With none seen, maybe
+/// for being hidden, the main network.
+fn choose(network: &Network) -> KnownNetwork {
+    SCAN.lock(|scan| {
+        let scan = scan.borrow();
+        network
+            .all()
+            .filter_map(|known| {
+                let rssi = scan
+                    .networks
+                    .iter()
+                    .filter(|v| v.ssid == known.ssid)
+                    .map(|v| v.rssi)
+                    .max()?;
+                Some((known, rssi))
+            })
+            .max_by_key(|(known, rssi)| (known.priority, *rssi))
+            .map(|(known, _)| known)
+    })
+    .unwrap_or_else(|| network.main())
+}
+
+/// Tries joining one of the known networks up to `attempts` times, scanning
+/// for them before each. Whether it worked.
+pub async fn join(control: &mut Control<'_>, network: &Network, attempts: u32) -> bool {
+    for _ in 0..attempts {
+        scan(control).await;
+        let chosen = choose(network);
+        match control
+            .join(&chosen.ssid, join_options(&chosen.password))
+            .await
+        {
+            Ok(_) => {
+                info!("Joined network {}", chosen.ssid.as_str());
+                STATUS.lock(|status| status.borrow_mut().ssid = chosen.ssid);
+                return true;
+            }
+            Err(err) => {
+                info!(
+                    "Joining {} failed with status = {}",
+                    chosen.ssid.as_str(),
+                    err.status
+                );
+            }
+        }
+    }
+    false
+}
+
+/// The stack configuration to start with on `network`
+pub fn stack_config(network: &Network) -> embassy_net::Config {
+    match network.mode {
+        IpMode::Static => embassy_net::Config::ipv4_static(static_config(network)),
+        IpMode::Dhcp => embassy_net::Config::dhcpv4(Default::default()),
+    }
+}
+
+/// Gets an address on a joined network, asking for a new lease in DHCP
+/// mode. Without one in time, the static address is used instead.
+pub async fn acquire(stack: Stack<'_>, network: &Network) {
+    let source = match network.mode {
+        IpMode::Static => {
+            stack.set_config_v4(ConfigV4::Static(static_config(network)));
+            Source::Static
+        }
+        IpMode::Dhcp => {
+            stack.set_config_v4(ConfigV4::Dhcp(Default::default()));
+            if with_timeout(LEASE_TIMEOUT, stack.wait_config_up())
+                .await
+                .is_ok()
+            {
+                Source::Dhcp
+            } else {
+                warn!(
+                    "No DHCP lease after {} s, using the static address",
+                    LEASE_TIMEOUT.as_secs()
+                );
+                stack.set_config_v4(ConfigV4::Static(static_config(network)));
+                Source::Fallback
+            }
+        }
+    };
+    STATUS.lock(|status| status.borrow_mut().source = source);
+}
+
+/// The address in use and where it comes from
+pub fn status() -> Status {
+    let mut status = STATUS.lock(|status| status.borrow().clone());
+    if provisioning::active() {
+        status.source = Source::AccessPoint;
+    }
+    status
+}
+
+/// Keeps track of the address in use, logging its changes such as a lease
+/// renewed with another address
+pub async fn watch(stack: Stack<'_>) -> ! {
+    loop {
+        let config = stack.config_v4();
+        let changed = STATUS.lock(|status| {
+            let mut status = status.borrow_mut();
+            let changed = status.config != config;
+            status.config = config.clone();
+            changed
+        });
+
+        if changed {
+            match &config {
+                Some(config) => info!(
+                    "Address {}, gateway {}, DNS {}",
+                    Display2Format(&config.address),
+                    Debug2Format(&config.gateway),
+                    Debug2Format(&config.dns_servers)
+                ),
+                None => info!("Address lost"),
+            }
+        }
+
+        Timer::after(WATCH_INTERVAL).await;
+    }
+}
+
+pub fn outages() -> Outages {
+    OUTAGES.lock(|outages| *outages.borrow())
+}
+
+/// Whether the station is joined and has an address. The cyw43 may not
+/// tell of an access point going away; the lease running out then does.
+fn connected(stack: Stack<'_>) -> bool {
+    stack.is_link_up() && stack.is_config_up()
+}
+
+/// Rejoins one of the known networks whenever the connection is lost,
+/// waiting longer after every failed attempt. An outage longer than set in
+/// the settings reboots the station.
+pub async fn supervise(mut control: Control<'_>, stack: Stack<'_>, network: Network) -> ! {
+    loop {
+        while connected(stack) {
+            Timer::after(SUPERVISE_INTERVAL).await;
+        }
+
+        warn!("Lost network {}", status().ssid.as_str());
+        OUTAGES.lock(|outages| outages.borrow_mut().start());
+        control.gpio_set(0, false).await;
+        let since = Instant::now();
+        let mut backoff = MIN_BACKOFF;
+
+        loop {
+            control.leave().await;
+            if join(&mut control, &network, 1).await {
+                acquire(stack, &network).await;
+                if connected(stack) {
+                    break;
+                }
+            }
+
+            let outage_reboot = config::get().await.outage_reboot;
+            if outage_reboot > 0 && since.elapsed().as_secs() >= outage_reboot as u64 * 60 {
+                warn!("Offline for {} min, rebooting", outage_reboot);
+                devices::system::schedule_reboot();
+            }
+
+            Timer::after(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+
+        OUTAGES.lock(|outages| outages.borrow_mut().end());
+        info!(
+            "Back on {} after {} s",
+            status().ssid.as_str(),
+            since.elapsed().as_secs()
+        );
+        control.gpio_set(0, true).await;
+    }
+}
@@ -0,0 +1,61 @@
+use embassy_sync::{blocking_mutex::raw::ThreadModeRawMutex, mutex::Mutex};
+use heapless::Deque;
+
+use crate::{
+    devices::rtc::Timestamp,
+    meteo::{self, PressureTendency},
+};
+
+/// Seconds between samples kept for the tendency
+pub const SAMPLE_INTERVAL: u32 = 10 * 60;
+const TENDENCY_PERIOD: u32 = 3 * 60 * 60;
+/// Enough samples to cover the tendency period with some slack
+const HISTORY_LENGTH: usize = (TENDENCY_PERIOD / SAMPLE_INTERVAL) as usize + 4;
+
+static HISTORY: Mutex<ThreadModeRawMutex, Deque<(Timestamp, i32), HISTORY_LENGTH>> =
+    Mutex::new(Deque::new());
+
+/// Stores a station pressure sample, in Pa. Samples are kept at most every
+/// `SAMPLE_INTERVAL`, so this can be called with every reading.
+pub async fn record(timestamp: Timestamp, pressure: i32) {
+    let mut history = HISTORY.lock().await;
+
+    match history.back().map(|(t, _)| *t) {
+        // The clock was set back - older samples can't be placed in time anymore
+        Some(t) if t > timestamp => history.clear(),
+        // Also skips a sample of the same second as the last one
+        Some(t) if timestamp - t < SAMPLE_INTERVAL => return,
+        _ => {}
+    }
+    if history.is_full() {
+        history.pop_front();
+    }
+    // Cannot fail - room was made above
+    let _ = history.push_back((timestamp, pressure));
+}
+
+fn sample_near(
+    history: &Deque<(Timestamp, i32), HISTORY_LENGTH>,
+    timestamp: Timestamp,
+) -> Option<i32> {
+    history
+        .iter()
+        .filter(|(t, _)| t.abs_diff(timestamp) <= SAMPLE_INTERVAL / 2)
+        .min_by_key(|(t, _)| t.abs_diff(timestamp))
+        .map(|(_, p)| *p)
+}
+
+/// Pressure tendency over the last three hours, once that much history exists
+pub async fn tendency() -> Option<PressureTendency> {
+    let history = HISTORY.lock().await;
+
+    let (now, pressure) = *history.back()?;
+    let three_hours_ago = sample_near(&history, now.checked_sub(TENDENCY_PERIOD)?)?;
+    let half_way = sample_near(&history, now - TENDENCY_PERIOD / 2)?;
+
+    Some(meteo::pressure_tendency(
+        three_hours_ago,
+        half_way,
+        pressure,
+    ))
+}
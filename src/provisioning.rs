@@ -0,0 +1,84 @@
+//! Setup mode, for stations that cannot join their network. The cyw43
+//! becomes an access point, hands out addresses and answers every name
+//! with its own, so that phones joining it open the setup page.
+
+pub mod dhcp;
+pub mod dns;
+
+use core::net::Ipv4Addr;
+
+use cyw43::Control;
+use defmt::{info, warn, Display2Format};
+use embassy_net::{ConfigV4, Ipv4Cidr, Stack, StaticConfigV4};
+use embassy_rp::gpio::Input;
+use embassy_time::{Duration, Timer};
+use heapless::Vec;
+use portable_atomic::{AtomicBool, Ordering};
+
+pub const SSID: &str = "weather-station-setup";
+/// The station's address on its own network, a /24
+pub const ADDRESS: Ipv4Addr = Ipv4Addr::new(192, 168, 4, 1);
+const PREFIX_LEN: u8 = 24;
+const CHANNEL: u8 = 6;
+/// Failed joins in a row before giving up on the network
+pub const JOIN_ATTEMPTS: u32 = 5;
+/// How long the setup button is held at power-up, in tenths of a second
+const BUTTON_HOLD: u32 = 30;
+/// The network may only have been down, e.g. with the router booting slower
+/// after a power cut
+const RETRY_AFTER: Duration = Duration::from_secs(15 * 60);
+
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Whether the station is an access point
+pub fn active() -> bool {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+/// Whether the setup button, pulling its pin low, is held for three
+/// seconds from power-up
+pub async fn button_held(button: &Input<'_>) -> bool {
+    for _ in 0..BUTTON_HOLD {
+        if button.is_high() {
+            return false;
+        }
+        Timer::after_millis(100).await;
+    }
+    true
+}
+
+/// Turns the cyw43 into an access point and the stack to its address. The
+/// DHCP and DNS servers are left to be run.
+///
+/// The network is secured with the admin password where WPA2 allows it,
+/// i.e. if it is 8 characters or longer, and open otherwise.
+pub async fn start(control: &mut Control<'_>, stack: Stack<'_>) {
+    ACTIVE.store(true, Ordering::Relaxed);
+
+    stack.set_config_v4(ConfigV4::Static(StaticConfigV4 {
+        address: Ipv4Cidr::new(ADDRESS, PREFIX_LEN),
+        gateway: None,
+        dns_servers: Vec::new(),
+    }));
+
+    if crate::ADMIN_PASSWORD.len() >= 8 {
+        control
+            .start_ap_wpa2(SSID, crate::ADMIN_PASSWORD, CHANNEL)
+            .await;
+    } else {
+        warn!("Admin password too short for WPA2, the setup network is open");
+        control.start_ap_open(SSID, CHANNEL).await;
+    }
+    info!(
+        "Started access point {} at {}",
+        SSID,
+        Display2Format(&ADDRESS)
+    );
+}
+
+/// Reboots to try joining again, unless setup has rebooted meanwhile
+pub async fn retry_later() {
+    Timer::after(RETRY_AFTER).await;
+    info!("Nothing set up, retrying the network");
+    crate::devices::system::schedule_reboot();
+}
@@ -0,0 +1,197 @@
+//! Just enough of a DHCP server (RFC 2131) for a few phones and laptops on
+//! the setup network. Clients get an address by their MAC address from a
+//! small pool, and the station as their router and DNS server.
+
+use core::net::Ipv4Addr;
+
+use defmt::{debug, warn, Display2Format};
+use embassy_net::{
+    udp::{PacketMetadata, UdpSocket},
+    IpEndpoint, Stack,
+};
+use heapless::Vec;
+
+use super::ADDRESS;
+
+const SERVER_PORT: u16 = 67;
+const CLIENT_PORT: u16 = 68;
+/// Clients get .2 to .9 of the station's /24
+const POOL_START: u8 = 2;
+const POOL_SIZE: usize = 8;
+const LEASE_SECS: u32 = 60 * 60;
+
+/// The fixed fields, up to and including the magic cookie
+const HEADER_SIZE: usize = 240;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+/// The smallest message every client must accept
+const MAX_MESSAGE: usize = 576;
+const BOOT_REPLY: u8 = 2;
+
+// Message types
+const DISCOVER: u8 = 1;
+const OFFER: u8 = 2;
+const REQUEST: u8 = 3;
+const ACK: u8 = 5;
+const NAK: u8 = 6;
+
+// Options
+const PAD: u8 = 0;
+const SUBNET_MASK: u8 = 1;
+const ROUTER: u8 = 3;
+const DNS_SERVER: u8 = 6;
+const REQUESTED_ADDRESS: u8 = 50;
+const LEASE_TIME: u8 = 51;
+const MESSAGE_TYPE: u8 = 53;
+const SERVER_ID: u8 = 54;
+const END: u8 = 255;
+
+/// The clients given addresses, by pool slot. When full, slots are reused
+/// oldest first.
+struct Leases {
+    clients: Vec<[u8; 6], POOL_SIZE>,
+    next: usize,
+}
+
+impl Leases {
+    const fn new() -> Self {
+        Self {
+            clients: Vec::new(),
+            next: 0,
+        }
+    }
+
+    fn address_for(&mut self, client: [u8; 6]) -> Ipv4Addr {
+        let slot = match self.clients.iter().position(|c| *c == client) {
+            Some(slot) => slot,
+            None if !self.clients.is_full() => {
+                // Cannot fail - not full
+                let _ = self.clients.push(client);
+                self.clients.len() - 1
+            }
+            None => {
+                let slot = self.next;
+                self.clients[slot] = client;
+                self.next = (slot + 1) % POOL_SIZE;
+                slot
+            }
+        };
+        let [a, b, c, _] = ADDRESS.octets();
+        Ipv4Addr::new(a, b, c, POOL_START + slot as u8)
+    }
+}
+
+/// Calls `f` with the code and value of every option of a message
+fn for_each_option<F: FnMut(u8, &[u8])>(options: &[u8], mut f: F) {
+    let mut i = 0;
+    while let Some(&code) = options.get(i) {
+        match code {
+            PAD => i += 1,
+            END => break,
+            _ => {
+                let Some(&length) = options.get(i + 1) else {
+                    break;
+                };
+                let Some(value) = options.get(i + 2..i + 2 + length as usize) else {
+                    break;
+                };
+                f(code, value);
+                i += 2 + length as usize;
+            }
+        }
+    }
+}
+
+/// Writes the reply to a client's message, if it needs one
+fn answer(message: &[u8], leases: &mut Leases, reply: &mut Vec<u8, MAX_MESSAGE>) -> Option<()> {
+    if message.len() < HEADER_SIZE || message[236..240] != MAGIC_COOKIE {
+        return None;
+    }
+    let client: [u8; 6] = message[28..34].try_into().ok()?;
+
+    let mut message_type = None;
+    let mut requested = None;
+    for_each_option(&message[HEADER_SIZE..], |code, value| match (code, value) {
+        (MESSAGE_TYPE, &[t]) => message_type = Some(t),
+        (REQUESTED_ADDRESS, &[a, b, c, d]) => requested = Some(Ipv4Addr::new(a, b, c, d)),
+        _ => {}
+    });
+    // A renewing client has its address in `ciaddr` instead
+    let current: [u8; 4] = message[12..16].try_into().ok()?;
+    let requested = requested.or(Some(Ipv4Addr::from(current)).filter(|a| !a.is_unspecified()));
+
+    let address = leases.address_for(client);
+    let reply_type = match message_type? {
+        DISCOVER => OFFER,
+        REQUEST if requested.is_some_and(|r| r != address) => NAK,
+        REQUEST => ACK,
+        _ => return None,
+    };
+    debug!(
+        "DHCP {} for {}: {}",
+        message_type,
+        client,
+        Display2Format(&address)
+    );
+
+    // Transaction id, flags and the client's hardware address as asked,
+    // the rest cleared
+    reply.resize(HEADER_SIZE, 0).ok()?;
+    reply[..4].copy_from_slice(&[BOOT_REPLY, message[1], message[2], 0]);
+    reply[4..8].copy_from_slice(&message[4..8]);
+    reply[10..12].copy_from_slice(&message[10..12]);
+    if reply_type != NAK {
+        reply[16..20].copy_from_slice(&address.octets());
+        reply[20..24].copy_from_slice(&ADDRESS.octets());
+    }
+    reply[28..44].copy_from_slice(&message[28..44]);
+    reply[236..240].copy_from_slice(&MAGIC_COOKIE);
+
+    let mut option = |code: u8, value: &[u8]| -> Option<()> {
+        reply.extend_from_slice(&[code, value.len() as u8]).ok()?;
+        reply.extend_from_slice(value).ok()
+    };
+    option(MESSAGE_TYPE, &[reply_type])?;
+    option(SERVER_ID, &ADDRESS.octets())?;
+    if reply_type != NAK {
+        option(LEASE_TIME, &LEASE_SECS.to_be_bytes())?;
+        option(SUBNET_MASK, &[255, 255, 255, 0])?;
+        option(ROUTER, &ADDRESS.octets())?;
+        option(DNS_SERVER, &ADDRESS.octets())?;
+    }
+    reply.push(END).ok()
+}
+
+/// Answers DHCP clients on the setup network
+pub async fn run(stack: Stack<'static>) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0; 2 * MAX_MESSAGE];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer = [0; 2 * MAX_MESSAGE];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    // Cannot fail - the port is free and not 0
+    socket.bind(SERVER_PORT).unwrap();
+
+    let mut leases = Leases::new();
+    let mut message = [0; MAX_MESSAGE];
+    let mut reply = Vec::new();
+    loop {
+        let Ok((length, _)) = socket.recv_from(&mut message).await else {
+            continue;
+        };
+        reply.clear();
+        if answer(&message[..length], &mut leases, &mut reply).is_none() {
+            continue;
+        }
+        // Clients have no address to be sent to yet
+        let to = IpEndpoint::new(Ipv4Addr::BROADCAST.into(), CLIENT_PORT);
+        if let Err(e) = socket.send_to(&reply, to).await {
+            warn!("Sending DHCP reply failed: {}", e);
+        }
+    }
+}
@@ -0,0 +1,101 @@
+//! A DNS responder answering every address query on the setup network with
+//! the station's address. Phones look up a name of their maker to tell
+//! whether a network is a captive portal, and so are shown the setup page.
+
+use defmt::warn;
+use embassy_net::{
+    udp::{PacketMetadata, UdpSocket},
+    Stack,
+};
+use heapless::Vec;
+
+use super::ADDRESS;
+
+const PORT: u16 = 53;
+/// The largest message over UDP without extensions
+const MAX_MESSAGE: usize = 512;
+const HEADER_SIZE: usize = 12;
+/// Response, recursion available, no error
+const RESPONSE_FLAGS: u16 = 0x8080;
+/// Recursion desired, copied from the query
+const RECURSION_DESIRED: u16 = 0x0100;
+const TYPE_A: u16 = 1;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+const TTL_SECS: u32 = 60;
+/// A pointer to the name of the question, right after the header
+const NAME_POINTER: [u8; 2] = [0xc0, HEADER_SIZE as u8];
+
+/// Writes the answer to a query of a single question, the station's
+/// address for an IPv4 address and none for anything else
+fn answer(query: &[u8], reply: &mut Vec<u8, MAX_MESSAGE>) -> Option<()> {
+    let header = query.get(..HEADER_SIZE)?;
+    let is_query = header[2] & 0x80 == 0;
+    let questions = u16::from_be_bytes([header[4], header[5]]);
+    if !is_query || questions != 1 {
+        return None;
+    }
+
+    // The name, as labels up to an empty one, then its type and class
+    let mut end = HEADER_SIZE;
+    loop {
+        let length = *query.get(end)? as usize;
+        end += 1 + length;
+        if length == 0 {
+            break;
+        }
+    }
+    let question = query.get(HEADER_SIZE..end + 4)?;
+    let kind = u16::from_be_bytes([query[end], query[end + 1]]);
+    let class = u16::from_be_bytes([query[end + 2], query[end + 3]]);
+    let answers = matches!(kind, TYPE_A | TYPE_ANY) && class == CLASS_IN;
+
+    reply.extend_from_slice(&header[..2]).ok()?;
+    let flags = RESPONSE_FLAGS | (u16::from_be_bytes([header[2], header[3]]) & RECURSION_DESIRED);
+    reply.extend_from_slice(&flags.to_be_bytes()).ok()?;
+    reply
+        .extend_from_slice(&[0, 1, 0, answers as u8, 0, 0, 0, 0])
+        .ok()?;
+    reply.extend_from_slice(question).ok()?;
+    if answers {
+        reply.extend_from_slice(&NAME_POINTER).ok()?;
+        reply.extend_from_slice(&TYPE_A.to_be_bytes()).ok()?;
+        reply.extend_from_slice(&CLASS_IN.to_be_bytes()).ok()?;
+        reply.extend_from_slice(&TTL_SECS.to_be_bytes()).ok()?;
+        reply.extend_from_slice(&4u16.to_be_bytes()).ok()?;
+        reply.extend_from_slice(&ADDRESS.octets()).ok()?;
+    }
+    Some(())
+}
+
+/// Answers DNS queries on the setup network
+pub async fn run(stack: Stack<'static>) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0; 2 * MAX_MESSAGE];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer = [0; 2 * MAX_MESSAGE];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    // Cannot fail - the port is free and not 0
+    socket.bind(PORT).unwrap();
+
+    let mut query = [0; MAX_MESSAGE];
+    let mut reply = Vec::new();
+    loop {
+        let Ok((length, from)) = socket.recv_from(&mut query).await else {
+            continue;
+        };
+        reply.clear();
+        if answer(&query[..length], &mut reply).is_none() {
+            continue;
+        }
+        if let Err(e) = socket.send_to(&reply, from).await {
+            warn!("Sending DNS reply failed: {}", e);
+        }
+    }
+}
@@ -0,0 +1,233 @@
+//! Rolling statistics of every measured quantity over the last hour, the
+//! last day and since local midnight
+
+use core::cell::RefCell;
+
+use defmt::warn;
+use embassy_sync::blocking_mutex::{raw::ThreadModeRawMutex, Mutex};
+use heapless::Vec;
+
+use crate::devices::{
+    rtc::Timestamp,
+    sensor::{Measurement, Quantity, Unit, MAX_SERIES},
+};
+
+const SECONDS_PER_DAY: u32 = 24 * 60 * 60;
+
+const SHORT_BIN: u32 = 5 * 60;
+const SHORT_BINS: usize = 12;
+const LONG_BIN: u32 = 60 * 60;
+const LONG_BINS: usize = 24;
+
+/// Count, mean, variance and extremes of a set of samples. Kept as mean
+/// and sum of squared deviations, which merge without losing precision.
+#[derive(Clone, Copy)]
+pub struct Summary {
+    pub count: u32,
+    pub mean: f32,
+    m2: f32,
+    pub min: (f32, Timestamp),
+    pub max: (f32, Timestamp),
+}
+
+impl Summary {
+    const EMPTY: Self = Self {
+        count: 0,
+        mean: 0.0,
+        m2: 0.0,
+        min: (f32::INFINITY, 0),
+        max: (f32::NEG_INFINITY, 0),
+    };
+
+    fn add(&mut self, value: f32, timestamp: Timestamp) {
+        self.merge(&Self {
+            count: 1,
+            mean: value,
+            m2: 0.0,
+            min: (value, timestamp),
+            max: (value, timestamp),
+        });
+    }
+
+    fn merge(&mut self, other: &Self) {
+        if other.count == 0 {
+            return;
+        }
+
+        let count = self.count + other.count;
+        let delta = other.mean - self.mean;
+        let weight = other.count as f32 / count as f32;
+        self.mean += delta * weight;
+        self.m2 += other.m2 + delta * delta * self.count as f32 * weight;
+        self.count = count;
+
+        if other.min.0 < self.min.0 {
+            self.min = other.min;
+        }
+        if other.max.0 > self.max.0 {
+            self.max = other.max;
+        }
+    }
+
+    /// Population standard deviation
+    pub fn stddev(&self) -> f32 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        libm::sqrtf(self.m2 / self.count as f32)
+    }
+}
+
+/// A summary of the samples taken in one period, the start of which
+/// identifies whether the bin is current
+#[derive(Clone, Copy)]
+struct Bin {
+    start: Timestamp,
+    summary: Summary,
+}
+
+/// Summaries of `N` consecutive periods of `PERIOD` seconds, reused in turn
+struct Bins<const PERIOD: u32, const N: usize> {
+    bins: [Bin; N],
+}
+
+impl<const PERIOD: u32, const N: usize> Bins<PERIOD, N> {
+    const EMPTY: Self = Self {
+        bins: [Bin {
+            start: 0,
+            summary: Summary::EMPTY,
+        }; N],
+    };
+
+    fn add(&mut self, value: f32, timestamp: Timestamp) {
+        let start = timestamp - timestamp % PERIOD;
+        let bin = &mut self.bins[(timestamp / PERIOD) as usize % N];
+        if bin.start != start {
+            *bin = Bin {
+                start,
+                summary: Summary::EMPTY,
+            };
+        }
+        bin.summary.add(value, timestamp);
+    }
+
+    /// Summary of the last `N` periods up to the one containing `now`
+    fn summary(&self, now: Timestamp) -> Summary {
+        let oldest = (now - now % PERIOD).saturating_sub((N as u32 - 1) * PERIOD);
+        let mut summary = Summary::EMPTY;
+        for bin in self
+            .bins
+            .iter()
+            .filter(|b| b.start >= oldest && b.start <= now)
+        {
+            summary.merge(&bin.summary);
+        }
+        summary
+    }
+}
+
+struct Series {
+    sensor: &'static str,
+    quantity: Quantity,
+    unit: Unit,
+    precision: u8,
+    hour: Bins<SHORT_BIN, SHORT_BINS>,
+    day: Bins<LONG_BIN, LONG_BINS>,
+    /// Day number (since 1970) `since_midnight` belongs to
+    today: u32,
+    since_midnight: Summary,
+}
+
+impl Series {
+    fn add(&mut self, value: f32, timestamp: Timestamp) {
+        self.hour.add(value, timestamp);
+        self.day.add(value, timestamp);
+
+        if timestamp / SECONDS_PER_DAY != self.today {
+            self.today = timestamp / SECONDS_PER_DAY;
+            self.since_midnight = Summary::EMPTY;
+        }
+        self.since_midnight.add(value, timestamp);
+    }
+}
+
+/// What a handler gets to see of a series
+pub struct Statistics {
+    pub sensor: &'static str,
+    pub quantity: Quantity,
+    pub unit: Unit,
+    /// Decimal places the sensor reports
+    pub precision: u8,
+    pub last_hour: Summary,
+    pub last_day: Summary,
+    pub since_midnight: Summary,
+}
+
+static SERIES: Mutex<ThreadModeRawMutex, RefCell<Vec<Series, MAX_SERIES>>> =
+    Mutex::new(RefCell::new(Vec::new()));
+
+/// Adds a timestamped measurement of `sensor` to its series
+pub fn record(sensor: &'static str, measurement: &Measurement) {
+    let Some(timestamp) = measurement.timestamp else {
+        return;
+    };
+    // The arithmetic mean of angles is meaningless, wind::wind() averages them
+    if measurement.quantity == Quantity::WindDirection {
+        return;
+    }
+
+    SERIES.lock(|cell| {
+        let mut series = cell.borrow_mut();
+        let i = match series
+            .iter()
+            .position(|s| s.sensor == sensor && s.quantity == measurement.quantity)
+        {
+            Some(i) => i,
+            None => {
+                let new = Series {
+                    sensor,
+                    quantity: measurement.quantity,
+                    unit: measurement.unit,
+                    precision: measurement.precision,
+                    hour: Bins::EMPTY,
+                    day: Bins::EMPTY,
+                    today: timestamp / SECONDS_PER_DAY,
+                    since_midnight: Summary::EMPTY,
+                };
+                if series.push(new).is_err() {
+                    warn!(
+                        "No room for statistics of {} {}",
+                        sensor, measurement.quantity
+                    );
+                    return;
+                }
+                series.len() - 1
+            }
+        };
+
+        series[i].add(measurement.as_f32(), timestamp);
+    });
+}
+
+/// The statistics of the series at `index` as of `now`, `None` past the last
+pub fn get(now: Timestamp, index: usize) -> Option<Statistics> {
+    SERIES.lock(|cell| {
+        let series = cell.borrow();
+        let series = series.get(index)?;
+        let since_midnight = if series.today == now / SECONDS_PER_DAY {
+            series.since_midnight
+        } else {
+            Summary::EMPTY
+        };
+
+        Some(Statistics {
+            sensor: series.sensor,
+            quantity: series.quantity,
+            unit: series.unit,
+            precision: series.precision,
+            last_hour: series.hour.summary(now),
+            last_day: series.day.summary(now),
+            since_midnight,
+        })
+    })
+}